@@ -1,11 +1,13 @@
 #![feature(slicing_syntax)]
 
-extern crate openssl;
 extern crate serialize;
 extern crate url;
 
 mod set1;
 mod set2;
+mod set3;
+mod set4;
+mod set7;
 mod stdlib_ext;
 mod toolbox;
 
@@ -1,6 +1,5 @@
 // Set 1 of the Matasano Cryptopals challenges
 
-use openssl;
 use serialize::base64;
 use serialize::base64::FromBase64;
 use serialize::base64::ToBase64;
@@ -11,8 +10,9 @@ use std::io::File;
 use std::iter::Iterator;
 use std::str;
 use stdlib_ext::PartialOrdIterator;
-use toolbox::xor;
 use toolbox::blocks;
+use toolbox::crypto;
+use toolbox::xor;
 use toolbox::lang::CharUnigrams;
 
 #[test]
@@ -129,11 +129,10 @@ fn challenge7()
         .from_base64()
         .unwrap();
 
-    let decrypted = openssl::crypto::symm::decrypt(
-        openssl::crypto::symm::AES_128_ECB,
+    let decrypted = crypto::ecb_decrypt(
         "YELLOW SUBMARINE".as_bytes(),
-        vec![],
-        ciphertext[]);
+        ciphertext[],
+        []);
 
     assert_eq!(
         "I'm back and",
@@ -60,6 +60,7 @@ fn challenge11() {
     for i in range(0u,100) {
         let use_ecb = rand(2) > 0;
         let detected_ecb_mode = toolbox::crypto::uses_ecb_mode(
+            16,
             |msg| oracle(use_ecb,msg));
 
         println!("{}: {},{}",i,detected_ecb_mode, use_ecb);
@@ -85,16 +86,22 @@ fn challenge12()
         ciphertext
     }
 
-    let oracle_uses_ecb = toolbox::crypto::uses_ecb_mode(oracle);
+    let oracle_uses_ecb = toolbox::crypto::uses_ecb_mode(16, oracle);
     assert_eq!(oracle_uses_ecb, true);
 
     let (oracle_block_size,oracle_suffix_len) = toolbox::blocks::analyze_oracle(oracle);
     assert_eq!(oracle_block_size,16);
     assert_eq!(oracle_suffix_len,138);
 
-    let decrypted = toolbox::crypto::ecb_suffix_decrypter(oracle);
+    let decrypted = toolbox::crypto::ecb_suffix_decrypter('A' as u8, oracle);
     assert_eq!(Some("Rollin' in my 5.0\nWith my rag-top down so my hair can blow"),
-               str::from_utf8(decrypted.slice(0,58)));
+               str::from_utf8(decrypted.plaintext.slice(0,58)));
+    assert_eq!(138, decrypted.plaintext.len());
+
+    // Stopping at the first match takes at most 128 candidates for each
+    // ASCII byte, plus 16 queries for the target blocks and at most 17 in
+    // analyze_oracle
+    assert!(decrypted.oracle_queries <= 128*138 + 16 + 17);
 }
 
 #[test]
@@ -155,8 +162,8 @@ fn challenge13()
 
     let mut admin_profile = Vec::<u8>::new();
     admin_profile.push_all(ciphertext1.slice(0,32));
-    admin_profile.push_all(ciphertext2.slice(16,32));
-    admin_profile.push_all(ciphertext1.slice(32,48));
+    admin_profile.push_all(toolbox::blocks::nth_block(16, ciphertext2[], 1));
+    admin_profile.push_all(toolbox::blocks::nth_block(16, ciphertext1[], 2));
 
     assert_eq!(true,
                is_encrypted_profile_admin(admin_profile[]));
@@ -164,3 +171,76 @@ fn challenge13()
     assert_eq!(Some("email=foo%40bar.com&uid=10&role=admin&uid=10&roluser"),
                str::from_utf8(toolbox::crypto::ecb_decrypt(key,admin_profile[],iv)[]));
 }
+
+#[test]
+fn challenge14()
+{
+    let key = Vec::from_fn(16,|_| rand::random::<u8>());
+    let prefix_len = rand::random::<uint>()%64;
+    let prefix = Vec::from_fn(prefix_len,|_| rand::random::<u8>());
+
+    let hidden_plaintext = "Um9sbGluJyBpbiBteSA1LjAKV2l0aCBteSByYWctdG9wIGRvd24gc28gbXkgaGFpciBjYW4gYmxvdwpUaGUgZ2lybGllcyBvbiBzdGFuZGJ5IHdhdmluZyBqdXN0IHRvIHNheSBoaQpEaWQgeW91IHN0b3A/IE5vLCBJIGp1c3QgZHJvdmUgYnkK"
+        .from_base64()
+        .unwrap();
+
+    let oracle = |data:&[u8]| -> Vec<u8> {
+        let mut plaintext = prefix.clone();
+        plaintext.push_all(data);
+        plaintext.push_all(hidden_plaintext[]);
+        toolbox::crypto::ecb_encrypt(key[],plaintext[],[0,..16])
+    };
+
+    assert_eq!(prefix_len,
+               toolbox::blocks::find_prefix_len(16, |msg| oracle(msg)));
+
+    let decrypted = toolbox::crypto::ecb_prefixed_suffix_decrypter('A' as u8, |msg| oracle(msg));
+    assert_eq!(hidden_plaintext, decrypted.plaintext);
+}
+
+#[test]
+fn challenge15()
+{
+    use toolbox::pad::PaddingError;
+
+    assert_eq!(Ok("ICE ICE BABY".as_bytes()),
+               toolbox::pad::pkcs7_unpad("ICE ICE BABY\x04\x04\x04\x04".as_bytes(),16));
+    assert_eq!(Err(PaddingError::BadPaddingByte('Y' as u8)),
+               toolbox::pad::pkcs7_unpad("ICE ICE BABY\x05\x05\x05\x05".as_bytes(),16));
+    assert_eq!(Err(PaddingError::BadPaddingByte(1)),
+               toolbox::pad::pkcs7_unpad("ICE ICE BABY\x01\x02\x03\x04".as_bytes(),16));
+}
+
+#[test]
+fn challenge16()
+{
+    let key = Vec::from_fn(16,|_| rand::random::<u8>());
+    let iv = Vec::from_fn(16,|_| rand::random::<u8>());
+
+    let encrypt_userdata = |userdata: &str| -> Vec<u8> {
+        let quoted = userdata.replace(";", "%3B")[].replace("=", "%3D");
+        let plaintext = format!(
+            "comment1=cooking%20MCs;userdata={};comment2=%20like%20a%20pound%20of%20bacon",
+            quoted);
+        toolbox::crypto::cbc_encrypt(key[], plaintext.as_bytes(), iv[])
+    };
+
+    let is_admin = |ciphertext: &[u8]| -> bool {
+        let plaintext = toolbox::crypto::cbc_decrypt(key[], ciphertext, iv[]);
+        plaintext.windows(12).any(|w| w == ";admin=true;".as_bytes())
+    };
+
+    assert_eq!(false, is_admin(encrypt_userdata(";admin=true;")[]));
+
+    // The prefix is exactly two blocks, so our data fills block 2
+    let ciphertext = encrypt_userdata("AAAAAAAAAAAAAAAA");
+    let plan = toolbox::crypto::plan_cbc_bitflip(
+        16,
+        iv[],
+        ciphertext[],
+        2,
+        "AAAAAAAAAAAAAAAA".as_bytes(),
+        "AAAAA;admin=true".as_bytes());
+
+    assert_eq!(Some(1), plan.scrambled_block);
+    assert_eq!(true, is_admin(plan.ciphertext[]));
+}
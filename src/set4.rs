@@ -0,0 +1,113 @@
+// Set 4 of the Matasano Cryptopals challenges
+
+use serialize::base64::FromBase64;
+use std::io::File;
+use std::rand;
+use toolbox::crypto;
+use toolbox::length_extension;
+use toolbox::md4;
+use toolbox::md4::Md4;
+use toolbox::sha1;
+use toolbox::sha1::Sha1;
+
+#[test]
+fn challenge25()
+{
+    let plaintext = crypto::ecb_decrypt(
+        "YELLOW SUBMARINE".as_bytes(),
+        File::open(&Path::new("data/data_s1c7.txt"))
+            .read_to_string()
+            .unwrap()[]
+            .from_base64()
+            .unwrap()[],
+        []);
+
+    let key = Vec::from_fn(16, |_| rand::random::<u8>());
+    let nonce = Vec::from_fn(8, |_| rand::random::<u8>());
+    let ciphertext = crypto::ctr_encrypt(key[], nonce[], plaintext[]);
+
+    for &max_edit_len in [1u, 16, 100, ciphertext.len()].iter()
+    {
+        let recovered = crypto::recover_ctr_plaintext_via_edit(
+            ciphertext[],
+            max_edit_len,
+            |offset, newtext| crypto::ctr_edit(ciphertext[], key[], nonce[], offset, newtext));
+        assert_eq!(plaintext, recovered);
+    }
+}
+
+#[test]
+fn challenge27()
+{
+    let key = Vec::from_fn(16, |_| rand::random::<u8>());
+
+    let plaintext = "comment1=cooking%20MCs;userdata=foo;comment2=%20like%20a%20pound%20of%20bacon";
+    let ciphertext = crypto::cbc_encrypt(key[], plaintext.as_bytes(), key[]);
+
+    // Complains about high-ASCII plaintext, helpfully including it
+    let oracle = |ciphertext: &[u8]| -> Result<(), Vec<u8>> {
+        let plaintext = match crypto::cbc_decrypt_checked(key[], ciphertext, key[]) {
+            Ok(plaintext) => plaintext,
+            Err(_) => return Ok(())
+        };
+        if plaintext.iter().any(|b| *b > 127) {
+            Err(plaintext)
+        } else {
+            Ok(())
+        }
+    };
+
+    assert_eq!(Ok(()), oracle(ciphertext[]));
+
+    let recovered = crypto::recover_key_used_as_iv(16, ciphertext[], |c| oracle(c));
+    assert_eq!(Some(key), recovered);
+}
+
+#[test]
+fn challenge28()
+{
+    let key = Vec::from_fn(16, |_| rand::random::<u8>());
+    let message = "comment1=cooking%20MCs;userdata=foo;comment2=%20like%20a%20pound%20of%20bacon".as_bytes();
+    let mac = sha1::secret_prefix_mac(key[], message);
+    assert_eq!(mac, sha1::secret_prefix_mac(key[], message));
+    let mut prefixed = key.clone();
+    prefixed.push_all(message);
+    assert_eq!(sha1::sha1(prefixed[]), mac);
+
+    // Changing the message or the key changes the MAC
+    let mut tampered = message.to_vec();
+    tampered[0] ^= 1;
+    assert!(mac != sha1::secret_prefix_mac(key[], tampered[]));
+    assert!(mac != sha1::secret_prefix_mac("YELLOW SUBMARINE".as_bytes(), message));
+    assert!(mac != sha1::sha1(message));
+}
+
+#[test]
+fn challenge29()
+{
+    let key_len = 1 + rand::random::<uint>() % 32;
+    let key = Vec::from_fn(key_len, |_| rand::random::<u8>());
+    let message = "comment1=cooking%20MCs;userdata=foo;comment2=%20like%20a%20pound%20of%20bacon".as_bytes();
+    let mac = sha1::secret_prefix_mac(key[], message);
+
+    let forged = length_extension::forge::<Sha1>(mac[], message, 0, 64, ";admin=true".as_bytes(),
+                                                  |m, t| sha1::secret_prefix_mac(key[], m)[] == t).unwrap();
+    assert_eq!(key_len, forged.key_len);
+    assert!(forged.message.ends_with(";admin=true".as_bytes()));
+    assert_eq!(sha1::secret_prefix_mac(key[], forged.message[]), forged.mac);
+}
+
+#[test]
+fn challenge30()
+{
+    let key_len = 1 + rand::random::<uint>() % 32;
+    let key = Vec::from_fn(key_len, |_| rand::random::<u8>());
+    let message = "comment1=cooking%20MCs;userdata=foo;comment2=%20like%20a%20pound%20of%20bacon".as_bytes();
+    let mac = md4::secret_prefix_mac(key[], message);
+
+    let forged = length_extension::forge::<Md4>(mac[], message, 0, 64, ";admin=true".as_bytes(),
+                                                 |m, t| md4::secret_prefix_mac(key[], m)[] == t).unwrap();
+    assert_eq!(key_len, forged.key_len);
+    assert!(forged.message.ends_with(";admin=true".as_bytes()));
+    assert_eq!(md4::secret_prefix_mac(key[], forged.message[]), forged.mac);
+}
@@ -0,0 +1,20 @@
+// Set 7 of the Matasano Cryptopals challenges
+
+use serialize::base64::FromBase64;
+use std::rand;
+use toolbox::rc4;
+
+// Takes 2^28 RC4 encryptions, so only run it on request
+#[test]
+#[ignore]
+fn challenge56()
+{
+    let cookie = "QkUgU1VSRSBUTyBEUklOSyBZT1VSIE9WQUxUSU5F".from_base64().unwrap();
+    let recovered = rc4::recover_cookie_from_biases(cookie.len(), 1 << 24, |request: &[u8]| {
+        let key = Vec::from_fn(16, |_| rand::random::<u8>());
+        let mut plaintext = request.to_vec();
+        plaintext.push_all(cookie[]);
+        rc4::rc4(key[], plaintext[])
+    });
+    assert_eq!(cookie, recovered.cookie);
+}
@@ -16,13 +16,31 @@ pub mod lang {
             let corpus = File::open(&Path::new(filename))
                 .read_to_string()
                 .unwrap();
+            CharUnigrams::from_chars(corpus[].chars().collect::<Vec<char>>()[])
+        }
+
+        // Only the first char of each sentence or line, which is mostly a
+        // capital letter. Good for scoring text we know starts a line.
+        pub fn new_initials(filename:&str) -> CharUnigrams {
+            let corpus = File::open(&Path::new(filename))
+                .read_to_string()
+                .unwrap();
+            let initials = corpus[]
+                .split(|c: char| c == '.' || c == '!' || c == '?' || c == '\n')
+                .map(|s| s.trim())
+                .filter(|s| s.len() > 0)
+                .map(|s| s.chars().next().unwrap())
+                .collect::<Vec<char>>();
+            CharUnigrams::from_chars(initials[])
+        }
 
+        fn from_chars(chars: &[char]) -> CharUnigrams {
             let mut counts: Vec<uint> = Vec::from_fn(MAX_CHAR_CODE,|_| 1);
             let mut total = MAX_CHAR_CODE;
-            for ch in corpus[].chars()
+            for ch in chars.iter()
             {
                 total += 1;
-                let ch_code = ch as uint;
+                let ch_code = *ch as uint;
                 if ch_code < MAX_CHAR_CODE
                 {
                     counts[ch_code] += 1;
@@ -116,7 +134,7 @@ pub mod xor {
     }
 
     pub fn find_best_xor_key(cipher: &[u8],scorer:|&[u8]|->f32) -> (f32,u8,Vec<u8>) {
-        range(0u,255)
+        range(0u,256)
             .map(|xor| xor as u8 )
             .map(|xor| (xor,de_xor(cipher,xor)) )
             .map(|(xor,plain)| (scorer(plain[]),xor,plain) )
@@ -165,11 +183,37 @@ pub mod xor {
             .map(|(_,xor,_)| xor)
             .collect::<Vec<u8>>()
     }
+
+    #[test]
+    fn test_find_best_xor_key()
+    {
+        // Every key byte is tried, including 0xff
+        let plaintext = "cooking mcs like a pound of bacon".as_bytes();
+        let scorer = |text: &[u8]| text.iter().filter(|c| **c == ' ' as u8 || (**c >= 'a' as u8 && **c <= 'z' as u8)).count() as f32;
+        for key in [0u8, 1, 0x58, 0xfe, 0xff].iter()
+        {
+            let (_,found,decrypted) = find_best_xor_key(de_xor(plaintext,*key)[], |t|scorer(t));
+            assert_eq!(*key, found);
+            assert_eq!(plaintext, decrypted[]);
+        }
+    }
 }
 
 pub mod blocks {
+    use std::cmp;
     use std::collections::HashMap;
 
+    pub fn nth_block(block_size: uint, data: &[u8], block_no: uint) -> &[u8] {
+        data.slice(block_no*block_size, (block_no+1)*block_size)
+    }
+
+    pub fn replace_block(block_size: uint, data: &mut [u8], block_no: uint, block: &[u8]) {
+        assert_eq!(block.len(), block_size);
+        for i in range(0u,block_size) {
+            data[block_no*block_size + i] = block[i];
+        }
+    }
+
     pub fn has_duplicate_blocks(block_size: uint, data: &[u8]) -> bool {
         count_duplicate_blocks(block_size, data) > 0
     }
@@ -204,6 +248,39 @@ pub mod blocks {
         panic!("failed to analyze_oracle");
     }
 
+    // Find the length of a fixed prefix which the oracle puts in front of
+    // our data. We feed it two blocks' worth of a repeated byte behind a
+    // growing amount of padding, until that lines up into two identical
+    // blocks whose position gives away the prefix length. We do this with
+    // two different bytes and only accept a pair of blocks which changes
+    // with the byte, so that neither duplicate blocks elsewhere in the
+    // message nor a prefix ending in the repeated byte can fool us.
+    pub fn find_prefix_len(block_size: uint, oracle: |data: &[u8]| -> Vec<u8>) -> uint {
+        for pad_len in range(0u,block_size)
+        {
+            let ciphertext_a = oracle(Vec::from_elem(pad_len + 2*block_size, 'A' as u8)[]);
+            let ciphertext_b = oracle(Vec::from_elem(pad_len + 2*block_size, 'B' as u8)[]);
+            if count_duplicate_blocks(block_size, ciphertext_a[]) == 0 ||
+               count_duplicate_blocks(block_size, ciphertext_b[]) == 0 {
+                continue;
+            }
+
+            let block_count = cmp::min(ciphertext_a.len(), ciphertext_b.len()) / block_size;
+            for block_no in range(1u,block_count)
+            {
+                let start = (block_no-1)*block_size;
+                let prev_a = nth_block(block_size, ciphertext_a[], block_no-1);
+                let prev_b = nth_block(block_size, ciphertext_b[], block_no-1);
+                let block_a = nth_block(block_size, ciphertext_a[], block_no);
+                let block_b = nth_block(block_size, ciphertext_b[], block_no);
+                if start >= pad_len && prev_a == block_a && prev_b == block_b && block_a != block_b {
+                    return start - pad_len;
+                }
+            }
+        }
+        panic!("failed to find_prefix_len");
+    }
+
     #[test]
     fn test_analyze_oracle()
     {
@@ -223,11 +300,46 @@ pub mod blocks {
             assert_eq!(found_block_size, 16);
         }
     }
+
+    #[test]
+    fn test_find_prefix_len()
+    {
+        let key = "yELlOW SUbMArInE".as_bytes();
+        let iv = [0, ..16];
+        for prefix_len in range(0u,40)
+        {
+            // a prefix ending in a run of 'A's shouldn't fool it
+            let prefix = Vec::from_fn(prefix_len, |i| if i + 3 >= prefix_len { 'A' as u8 } else { i as u8 });
+
+            let found_prefix_len = find_prefix_len(16, |msg: &[u8]| {
+                let mut plaintext = prefix.clone();
+                plaintext.push_all(msg);
+                plaintext.push_all("some hidden suffix".as_bytes());
+                super::crypto::ecb_encrypt(key,plaintext[],iv)
+            });
+
+            assert_eq!(found_prefix_len, prefix_len);
+        }
+    }
 }
 
 pub mod pad {
+    #[deriving(Show, PartialEq, Clone)]
+    pub enum PaddingError {
+        // The data is empty or not a whole number of blocks long
+        BadLength(uint),
+        // The final byte is zero or bigger than a block
+        BadPaddingLength(u8),
+        // A byte inside the padding doesn't match the final byte
+        BadPaddingByte(u8),
+        // A padding oracle accepted no guess for the byte at this position
+        // of a block, so it isn't behaving like a padding oracle
+        OracleRejectedAll(uint)
+    }
+
     pub trait Pkcs7Padding {
         fn pkcs7_extend(&mut self, block_size:uint) -> ();
+        fn pkcs7_trim(&mut self, block_size:uint) -> Result<(), PaddingError>;
     }
 
     impl Pkcs7Padding for Vec<u8> {
@@ -239,6 +351,12 @@ pub mod pad {
                 self.push(padding as u8)
             }
         }
+
+        fn pkcs7_trim(&mut self, block_size:uint) -> Result<(), PaddingError> {
+            let unpadded_len = try!(pkcs7_unpad(self[], block_size)).len();
+            self.truncate(unpadded_len);
+            Ok(())
+        }
     }
 
     pub fn pkcs7(data: &[u8], block_size:uint) -> Vec<u8> {
@@ -247,6 +365,28 @@ pub mod pad {
         padded
     }
 
+    // Check every padding byte, and return the data without the padding
+    pub fn pkcs7_unpad(data: &[u8], block_size:uint) -> Result<&[u8], PaddingError> {
+        let data_len = data.len();
+        if data_len == 0 || data_len % block_size != 0 {
+            return Err(PaddingError::BadLength(data_len));
+        }
+
+        let padding = data[data_len-1];
+        if padding == 0 || padding as uint > block_size {
+            return Err(PaddingError::BadPaddingLength(padding));
+        }
+
+        let unpadded_len = data_len - padding as uint;
+        for b in data.slice_from(unpadded_len).iter()
+        {
+            if *b != padding {
+                return Err(PaddingError::BadPaddingByte(*b));
+            }
+        }
+        Ok(data.slice_to(unpadded_len))
+    }
+
     #[test]
     fn test_pkcs7_padding()
     {
@@ -259,178 +399,4638 @@ pub mod pad {
         assert_eq!("12345678901234567890\x14\x14\x14\x14\x14\x14\x14\x14\x14\x14\x14\x14\x14\x14\x14\x14\x14\x14\x14\x14".as_bytes(),
                     pkcs7("12345678901234567890".as_bytes(),20)[]);
     }
+
+    #[test]
+    fn test_pkcs7_unpad()
+    {
+        assert_eq!(Ok("1234567890123456789".as_bytes()),
+                   pkcs7_unpad("1234567890123456789\x01".as_bytes(),20));
+        assert_eq!(Ok("".as_bytes()),
+                   pkcs7_unpad("\x04\x04\x04\x04".as_bytes(),4));
+
+        assert_eq!(Err(PaddingError::BadLength(0)),
+                   pkcs7_unpad("".as_bytes(),4));
+        assert_eq!(Err(PaddingError::BadLength(5)),
+                   pkcs7_unpad("1234\x01".as_bytes(),4));
+        assert_eq!(Err(PaddingError::BadPaddingLength(0)),
+                   pkcs7_unpad("123\x00".as_bytes(),4));
+        assert_eq!(Err(PaddingError::BadPaddingLength(5)),
+                   pkcs7_unpad("123\x05".as_bytes(),4));
+        assert_eq!(Err(PaddingError::BadPaddingByte(3)),
+                   pkcs7_unpad("12\x03\x02".as_bytes(),4));
+
+        let mut padded = pkcs7("YELLOW".as_bytes(), 8);
+        assert_eq!(Ok(()), padded.pkcs7_trim(8));
+        assert_eq!("YELLOW".as_bytes(), padded[]);
+    }
 }
 
-pub mod crypto {
-    use openssl;
-    use super::pad::Pkcs7Padding;
+pub mod aes {
+    // AES as described in FIPS-197, working on a single 16 byte block
+    // at a time. The state is kept in the same column-major byte order as
+    // the input block, so state[r + 4*c] is row r of column c.
 
-    // Implement cbc mode decrypt on top of openssl ecb mode
-    pub fn cbc_decrypt(key:&[u8], data: &[u8], iv:&[u8]) -> Vec<u8> {
-        let block_size = 16u;
-        let mut decrypted: Vec<u8> = Vec::new();
+    use super::crypto::{BlockCipher, InvalidKeyLength};
 
-        // data should already be padded to a multiple of the block_size.
-        // We preprocess the data by prefixing with the iv to act as
-        // simulated previous block of ciphertext, and suffixing with
-        // the iv to provide a sacrificial block at the end so that
-        // we can ask the openssl routine to decrypt 2 blocks and throw the second away
-        // in order to avoid it attempting to strip padding on a non-final block
-        assert_eq!(iv.len(), block_size);
-        assert_eq!(data.len() % block_size, 0);
-        let mut preproc = iv.to_vec();
-        preproc.push_all(data);
-        preproc.push_all(iv);
-        assert_eq!(preproc.len() % block_size, 0);
+    pub const BLOCK_SIZE: uint = 16;
+
+    static SBOX: [u8, ..256] = [
+        0x63, 0x7c, 0x77, 0x7b, 0xf2, 0x6b, 0x6f, 0xc5, 0x30, 0x01, 0x67, 0x2b, 0xfe, 0xd7, 0xab, 0x76,
+        0xca, 0x82, 0xc9, 0x7d, 0xfa, 0x59, 0x47, 0xf0, 0xad, 0xd4, 0xa2, 0xaf, 0x9c, 0xa4, 0x72, 0xc0,
+        0xb7, 0xfd, 0x93, 0x26, 0x36, 0x3f, 0xf7, 0xcc, 0x34, 0xa5, 0xe5, 0xf1, 0x71, 0xd8, 0x31, 0x15,
+        0x04, 0xc7, 0x23, 0xc3, 0x18, 0x96, 0x05, 0x9a, 0x07, 0x12, 0x80, 0xe2, 0xeb, 0x27, 0xb2, 0x75,
+        0x09, 0x83, 0x2c, 0x1a, 0x1b, 0x6e, 0x5a, 0xa0, 0x52, 0x3b, 0xd6, 0xb3, 0x29, 0xe3, 0x2f, 0x84,
+        0x53, 0xd1, 0x00, 0xed, 0x20, 0xfc, 0xb1, 0x5b, 0x6a, 0xcb, 0xbe, 0x39, 0x4a, 0x4c, 0x58, 0xcf,
+        0xd0, 0xef, 0xaa, 0xfb, 0x43, 0x4d, 0x33, 0x85, 0x45, 0xf9, 0x02, 0x7f, 0x50, 0x3c, 0x9f, 0xa8,
+        0x51, 0xa3, 0x40, 0x8f, 0x92, 0x9d, 0x38, 0xf5, 0xbc, 0xb6, 0xda, 0x21, 0x10, 0xff, 0xf3, 0xd2,
+        0xcd, 0x0c, 0x13, 0xec, 0x5f, 0x97, 0x44, 0x17, 0xc4, 0xa7, 0x7e, 0x3d, 0x64, 0x5d, 0x19, 0x73,
+        0x60, 0x81, 0x4f, 0xdc, 0x22, 0x2a, 0x90, 0x88, 0x46, 0xee, 0xb8, 0x14, 0xde, 0x5e, 0x0b, 0xdb,
+        0xe0, 0x32, 0x3a, 0x0a, 0x49, 0x06, 0x24, 0x5c, 0xc2, 0xd3, 0xac, 0x62, 0x91, 0x95, 0xe4, 0x79,
+        0xe7, 0xc8, 0x37, 0x6d, 0x8d, 0xd5, 0x4e, 0xa9, 0x6c, 0x56, 0xf4, 0xea, 0x65, 0x7a, 0xae, 0x08,
+        0xba, 0x78, 0x25, 0x2e, 0x1c, 0xa6, 0xb4, 0xc6, 0xe8, 0xdd, 0x74, 0x1f, 0x4b, 0xbd, 0x8b, 0x8a,
+        0x70, 0x3e, 0xb5, 0x66, 0x48, 0x03, 0xf6, 0x0e, 0x61, 0x35, 0x57, 0xb9, 0x86, 0xc1, 0x1d, 0x9e,
+        0xe1, 0xf8, 0x98, 0x11, 0x69, 0xd9, 0x8e, 0x94, 0x9b, 0x1e, 0x87, 0xe9, 0xce, 0x55, 0x28, 0xdf,
+        0x8c, 0xa1, 0x89, 0x0d, 0xbf, 0xe6, 0x42, 0x68, 0x41, 0x99, 0x2d, 0x0f, 0xb0, 0x54, 0xbb, 0x16,
+    ];
+    static INV_SBOX: [u8, ..256] = [
+        0x52, 0x09, 0x6a, 0xd5, 0x30, 0x36, 0xa5, 0x38, 0xbf, 0x40, 0xa3, 0x9e, 0x81, 0xf3, 0xd7, 0xfb,
+        0x7c, 0xe3, 0x39, 0x82, 0x9b, 0x2f, 0xff, 0x87, 0x34, 0x8e, 0x43, 0x44, 0xc4, 0xde, 0xe9, 0xcb,
+        0x54, 0x7b, 0x94, 0x32, 0xa6, 0xc2, 0x23, 0x3d, 0xee, 0x4c, 0x95, 0x0b, 0x42, 0xfa, 0xc3, 0x4e,
+        0x08, 0x2e, 0xa1, 0x66, 0x28, 0xd9, 0x24, 0xb2, 0x76, 0x5b, 0xa2, 0x49, 0x6d, 0x8b, 0xd1, 0x25,
+        0x72, 0xf8, 0xf6, 0x64, 0x86, 0x68, 0x98, 0x16, 0xd4, 0xa4, 0x5c, 0xcc, 0x5d, 0x65, 0xb6, 0x92,
+        0x6c, 0x70, 0x48, 0x50, 0xfd, 0xed, 0xb9, 0xda, 0x5e, 0x15, 0x46, 0x57, 0xa7, 0x8d, 0x9d, 0x84,
+        0x90, 0xd8, 0xab, 0x00, 0x8c, 0xbc, 0xd3, 0x0a, 0xf7, 0xe4, 0x58, 0x05, 0xb8, 0xb3, 0x45, 0x06,
+        0xd0, 0x2c, 0x1e, 0x8f, 0xca, 0x3f, 0x0f, 0x02, 0xc1, 0xaf, 0xbd, 0x03, 0x01, 0x13, 0x8a, 0x6b,
+        0x3a, 0x91, 0x11, 0x41, 0x4f, 0x67, 0xdc, 0xea, 0x97, 0xf2, 0xcf, 0xce, 0xf0, 0xb4, 0xe6, 0x73,
+        0x96, 0xac, 0x74, 0x22, 0xe7, 0xad, 0x35, 0x85, 0xe2, 0xf9, 0x37, 0xe8, 0x1c, 0x75, 0xdf, 0x6e,
+        0x47, 0xf1, 0x1a, 0x71, 0x1d, 0x29, 0xc5, 0x89, 0x6f, 0xb7, 0x62, 0x0e, 0xaa, 0x18, 0xbe, 0x1b,
+        0xfc, 0x56, 0x3e, 0x4b, 0xc6, 0xd2, 0x79, 0x20, 0x9a, 0xdb, 0xc0, 0xfe, 0x78, 0xcd, 0x5a, 0xf4,
+        0x1f, 0xdd, 0xa8, 0x33, 0x88, 0x07, 0xc7, 0x31, 0xb1, 0x12, 0x10, 0x59, 0x27, 0x80, 0xec, 0x5f,
+        0x60, 0x51, 0x7f, 0xa9, 0x19, 0xb5, 0x4a, 0x0d, 0x2d, 0xe5, 0x7a, 0x9f, 0x93, 0xc9, 0x9c, 0xef,
+        0xa0, 0xe0, 0x3b, 0x4d, 0xae, 0x2a, 0xf5, 0xb0, 0xc8, 0xeb, 0xbb, 0x3c, 0x83, 0x53, 0x99, 0x61,
+        0x17, 0x2b, 0x04, 0x7e, 0xba, 0x77, 0xd6, 0x26, 0xe1, 0x69, 0x14, 0x63, 0x55, 0x21, 0x0c, 0x7d,
+    ];
 
-        let block_count = preproc.len() / block_size;
-        for block_no in range(1u,block_count - 1)
+    static RCON: [u8, ..10] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+
+    #[deriving(Clone)]
+    pub struct Aes {
+        rounds: uint,
+        round_keys: Vec<[u8, ..16]>
+    }
+
+    impl Aes {
+        // The key length picks between AES-128, AES-192 and AES-256, and
+        // any other length is rejected
+        pub fn new(key: &[u8]) -> Result<Aes, InvalidKeyLength> {
+            let key_words = match key.len() {
+                16 | 24 | 32 => key.len() / 4,
+                other => return Err(InvalidKeyLength(other))
+            };
+            let rounds = key_words + 6;
+
+            // Expand the key into 4 words per round (plus the initial
+            // AddRoundKey), then regroup them into one 16 byte key per round
+            let word_count = 4*(rounds + 1);
+            let mut words: Vec<[u8, ..4]> = Vec::with_capacity(word_count);
+            for i in range(0u,word_count)
+            {
+                if i < key_words {
+                    words.push([key[4*i], key[4*i + 1], key[4*i + 2], key[4*i + 3]]);
+                    continue;
+                }
+
+                let mut temp = words[i-1];
+                if i % key_words == 0 {
+                    temp = [SBOX[temp[1] as uint] ^ RCON[i/key_words - 1],
+                            SBOX[temp[2] as uint],
+                            SBOX[temp[3] as uint],
+                            SBOX[temp[0] as uint]];
+                } else if key_words > 6 && i % key_words == 4 {
+                    temp = [SBOX[temp[0] as uint],
+                            SBOX[temp[1] as uint],
+                            SBOX[temp[2] as uint],
+                            SBOX[temp[3] as uint]];
+                }
+                let prev = words[i-key_words];
+                words.push([prev[0] ^ temp[0],
+                            prev[1] ^ temp[1],
+                            prev[2] ^ temp[2],
+                            prev[3] ^ temp[3]]);
+            }
+
+            let mut round_keys: Vec<[u8, ..16]> = Vec::with_capacity(rounds + 1);
+            for round in range(0u,rounds + 1)
+            {
+                let mut round_key = [0u8, ..16];
+                for i in range(0u,16) {
+                    round_key[i] = words[4*round + i/4][i%4];
+                }
+                round_keys.push(round_key);
+            }
+            Ok(Aes { rounds: rounds, round_keys: round_keys })
+        }
+
+        pub fn rounds(&self) -> uint {
+            self.rounds
+        }
+
+        pub fn round_key(&self, round: uint) -> &[u8] {
+            self.round_keys[round][]
+        }
+
+        // Encrypt a block, handing the state at the end of each round
+        // (round 0 being the initial AddRoundKey) to the tracer
+        pub fn encrypt_block_traced(&self, block: &mut [u8], tracer: |uint, &[u8]|) {
+            assert_eq!(block.len(), BLOCK_SIZE);
+
+            add_round_key(block, self.round_keys[0][]);
+            tracer(0, block);
+            for round in range(1u,self.rounds + 1)
+            {
+                sub_bytes(block);
+                shift_rows(block);
+                if round != self.rounds {
+                    mix_columns(block);
+                }
+                add_round_key(block, self.round_keys[round][]);
+                tracer(round, block);
+            }
+        }
+    }
+
+    impl BlockCipher for Aes {
+        fn block_size(&self) -> uint {
+            BLOCK_SIZE
+        }
+
+        fn encrypt_block(&self, block: &mut [u8]) {
+            self.encrypt_block_traced(block, |_,_| ());
+        }
+
+        fn decrypt_block(&self, block: &mut [u8]) {
+            assert_eq!(block.len(), BLOCK_SIZE);
+
+            add_round_key(block, self.round_keys[self.rounds][]);
+            for round in range(1u,self.rounds + 1).rev()
+            {
+                inv_shift_rows(block);
+                inv_sub_bytes(block);
+                add_round_key(block, self.round_keys[round - 1][]);
+                if round != 1 {
+                    inv_mix_columns(block);
+                }
+            }
+        }
+    }
+
+    pub fn add_round_key(state: &mut [u8], round_key: &[u8]) {
+        for i in range(0u,16) {
+            state[i] ^= round_key[i];
+        }
+    }
+
+    pub fn sub_bytes(state: &mut [u8]) {
+        for i in range(0u,16) {
+            state[i] = SBOX[state[i] as uint];
+        }
+    }
+
+    pub fn inv_sub_bytes(state: &mut [u8]) {
+        for i in range(0u,16) {
+            state[i] = INV_SBOX[state[i] as uint];
+        }
+    }
+
+    // Row r is rotated left by r positions
+    pub fn shift_rows(state: &mut [u8]) {
+        let old = state.to_vec();
+        for row in range(0u,4) {
+            for col in range(0u,4) {
+                state[row + 4*col] = old[row + 4*((col + row) % 4)];
+            }
+        }
+    }
+
+    pub fn inv_shift_rows(state: &mut [u8]) {
+        let old = state.to_vec();
+        for row in range(0u,4) {
+            for col in range(0u,4) {
+                state[row + 4*((col + row) % 4)] = old[row + 4*col];
+            }
+        }
+    }
+
+    pub fn mix_columns(state: &mut [u8]) {
+        for col in range(0u,4) {
+            let a = state.slice(4*col, 4*col + 4).to_vec();
+            state[4*col + 0] = gmul(a[0],2) ^ gmul(a[1],3) ^ a[2] ^ a[3];
+            state[4*col + 1] = a[0] ^ gmul(a[1],2) ^ gmul(a[2],3) ^ a[3];
+            state[4*col + 2] = a[0] ^ a[1] ^ gmul(a[2],2) ^ gmul(a[3],3);
+            state[4*col + 3] = gmul(a[0],3) ^ a[1] ^ a[2] ^ gmul(a[3],2);
+        }
+    }
+
+    pub fn inv_mix_columns(state: &mut [u8]) {
+        for col in range(0u,4) {
+            let a = state.slice(4*col, 4*col + 4).to_vec();
+            state[4*col + 0] = gmul(a[0],14) ^ gmul(a[1],11) ^ gmul(a[2],13) ^ gmul(a[3],9);
+            state[4*col + 1] = gmul(a[0],9) ^ gmul(a[1],14) ^ gmul(a[2],11) ^ gmul(a[3],13);
+            state[4*col + 2] = gmul(a[0],13) ^ gmul(a[1],9) ^ gmul(a[2],14) ^ gmul(a[3],11);
+            state[4*col + 3] = gmul(a[0],11) ^ gmul(a[1],13) ^ gmul(a[2],9) ^ gmul(a[3],14);
+        }
+    }
+
+    // Multiplication in GF(2^8) modulo x^8 + x^4 + x^3 + x + 1
+    fn gmul(a: u8, b: u8) -> u8 {
+        let mut a = a;
+        let mut b = b;
+        let mut product = 0u8;
+        while b != 0
         {
-            let block_start = block_no*block_size;
+            if (b & 1) == 1 {
+                product ^= a;
+            }
+            let high_bit = a & 0x80;
+            a <<= 1;
+            if high_bit != 0 {
+                a ^= 0x1b;
+            }
+            b >>= 1;
+        }
+        product
+    }
 
-            // ECB decrypt the block and a sacrificial block
-            let mut decrypted_block = openssl::crypto::symm::decrypt(
-                openssl::crypto::symm::AES_128_ECB,
-                key,
-                Vec::new(),
-                preproc.slice(block_start,block_start + 2*block_size));
+    #[test]
+    fn test_aes_fips197()
+    {
+        use serialize::hex::FromHex;
 
-            // XOR with previous ciphertext block to turn it into CBC
-            // Also trim everything after the first block
-            decrypted_block = super::xor::repeat_key_xor(
-                preproc.slice(block_start - block_size,block_start),
-                decrypted_block.slice(0,block_size));
-            
-            // Store the single decrypted block
-            decrypted.push_all(decrypted_block.slice(0,block_size));
+        // Appendix A.1 key expansion
+        let cipher = Aes::new("2b7e151628aed2a6abf7158809cf4f3c".from_hex().unwrap()[]).unwrap();
+        assert_eq!(10, cipher.rounds());
+        assert_eq!("d014f9a8c9ee2589e13f0cc8b6630ca6".from_hex().unwrap()[],
+                   cipher.round_key(10));
+
+        // Appendix B, including the state at the end of the first round
+        let mut block = "3243f6a8885a308d313198a2e0370734".from_hex().unwrap();
+        let mut states: Vec<Vec<u8>> = Vec::new();
+        cipher.encrypt_block_traced(block.as_mut_slice(), |_,state| states.push(state.to_vec()));
+        assert_eq!("3925841d02dc09fbdc118597196a0b32".from_hex().unwrap(), block);
+        assert_eq!(11, states.len());
+        assert_eq!("193de3bea0f4e22b9ac68d2ae9f84808".from_hex().unwrap(), states[0]);
+        assert_eq!("a49c7ff2689f352b6b5bea43026a5049".from_hex().unwrap(), states[1]);
+
+        // Appendix C.1 to C.3, one for each key size
+        let vectors = [
+            ("000102030405060708090a0b0c0d0e0f",
+             "69c4e0d86a7b0430d8cdb78070b4c55a"),
+            ("000102030405060708090a0b0c0d0e0f1011121314151617",
+             "dda97ca4864cdfe06eaf70a0ec0d7191"),
+            ("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+             "8ea2b7ca516745bfeafc49904b496089")];
+        for &(key, ciphertext) in vectors.iter()
+        {
+            let cipher = Aes::new(key.from_hex().unwrap()[]).unwrap();
+            let mut block = "00112233445566778899aabbccddeeff".from_hex().unwrap();
+            cipher.encrypt_block(block.as_mut_slice());
+            assert_eq!(ciphertext.from_hex().unwrap(), block);
+            cipher.decrypt_block(block.as_mut_slice());
+            assert_eq!("00112233445566778899aabbccddeeff".from_hex().unwrap(), block);
         }
+    }
 
-        // Remove padding at the end
-        let decrypted_len = decrypted.len();
-        let padding = decrypted[decrypted_len-1] as uint;
-        decrypted.slice(0,decrypted_len-padding).to_vec()
+    #[test]
+    fn test_aes_invalid_key_length()
+    {
+        assert_eq!(InvalidKeyLength(15), Aes::new([0u8, ..15]).err().unwrap());
+        assert_eq!(InvalidKeyLength(20), Aes::new([0u8, ..20]).err().unwrap());
+        assert_eq!(InvalidKeyLength(0), Aes::new([]).err().unwrap());
     }
+}
 
-    // Implement cbc mode decrypt on top of openssl ecb mode
-    pub fn cbc_encrypt(key:&[u8], data: &[u8], iv:&[u8]) -> Vec<u8> {
-        let block_size = 16u;
-        let mut encrypted: Vec<u8> = Vec::new();
+pub mod des {
+    // DES as described in FIPS 46-3, working on a single 8 byte block at a
+    // time, and Triple DES built out of it. The tables number bits from 1
+    // at the most significant end, the same way the standard does.
 
-        assert_eq!(iv.len(), block_size);
-        let mut prev_cipherblock = iv.to_vec();
-        let mut preproc = data.to_vec();
-        preproc.pkcs7_extend(block_size);
+    use super::crypto::{BlockCipher, InvalidKeyLength};
 
-        let block_count = preproc.len() / block_size;
-        for block_no in range(0u,block_count)
+    pub const BLOCK_SIZE: uint = 8;
+
+    static IP: [u8, ..64] = [
+        58, 50, 42, 34, 26, 18, 10,  2, 60, 52, 44, 36, 28, 20, 12,  4,
+        62, 54, 46, 38, 30, 22, 14,  6, 64, 56, 48, 40, 32, 24, 16,  8,
+        57, 49, 41, 33, 25, 17,  9,  1, 59, 51, 43, 35, 27, 19, 11,  3,
+        61, 53, 45, 37, 29, 21, 13,  5, 63, 55, 47, 39, 31, 23, 15,  7];
+
+    static FP: [u8, ..64] = [
+        40,  8, 48, 16, 56, 24, 64, 32, 39,  7, 47, 15, 55, 23, 63, 31,
+        38,  6, 46, 14, 54, 22, 62, 30, 37,  5, 45, 13, 53, 21, 61, 29,
+        36,  4, 44, 12, 52, 20, 60, 28, 35,  3, 43, 11, 51, 19, 59, 27,
+        34,  2, 42, 10, 50, 18, 58, 26, 33,  1, 41,  9, 49, 17, 57, 25];
+
+    static E: [u8, ..48] = [
+        32,  1,  2,  3,  4,  5,  4,  5,  6,  7,  8,  9,  8,  9, 10, 11,
+        12, 13, 12, 13, 14, 15, 16, 17, 16, 17, 18, 19, 20, 21, 20, 21,
+        22, 23, 24, 25, 24, 25, 26, 27, 28, 29, 28, 29, 30, 31, 32,  1];
+
+    static P: [u8, ..32] = [
+        16,  7, 20, 21, 29, 12, 28, 17,  1, 15, 23, 26,  5, 18, 31, 10,
+         2,  8, 24, 14, 32, 27,  3,  9, 19, 13, 30,  6, 22, 11,  4, 25];
+
+    static PC1: [u8, ..56] = [
+        57, 49, 41, 33, 25, 17,  9,  1, 58, 50, 42, 34, 26, 18, 10,  2,
+        59, 51, 43, 35, 27, 19, 11,  3, 60, 52, 44, 36, 63, 55, 47, 39,
+        31, 23, 15,  7, 62, 54, 46, 38, 30, 22, 14,  6, 61, 53, 45, 37,
+        29, 21, 13,  5, 28, 20, 12,  4];
+
+    static PC2: [u8, ..48] = [
+        14, 17, 11, 24,  1,  5,  3, 28, 15,  6, 21, 10, 23, 19, 12,  4,
+        26,  8, 16,  7, 27, 20, 13,  2, 41, 52, 31, 37, 47, 55, 30, 40,
+        51, 45, 33, 48, 44, 49, 39, 56, 34, 53, 46, 42, 50, 36, 29, 32];
+
+    static SHIFTS: [uint, ..16] = [1, 1, 2, 2, 2, 2, 2, 2, 1, 2, 2, 2, 2, 2, 2, 1];
+
+    // Each S-box is four rows of 16, indexed by the outer two bits of its
+    // 6 bit input for the row and the middle four for the column
+    static SBOXES: [[u8, ..64], ..8] = [
+         [14,  4, 13,  1,  2, 15, 11,  8,  3, 10,  6, 12,  5,  9,  0,  7,
+           0, 15,  7,  4, 14,  2, 13,  1, 10,  6, 12, 11,  9,  5,  3,  8,
+           4,  1, 14,  8, 13,  6,  2, 11, 15, 12,  9,  7,  3, 10,  5,  0,
+          15, 12,  8,  2,  4,  9,  1,  7,  5, 11,  3, 14, 10,  0,  6, 13],
+         [15,  1,  8, 14,  6, 11,  3,  4,  9,  7,  2, 13, 12,  0,  5, 10,
+           3, 13,  4,  7, 15,  2,  8, 14, 12,  0,  1, 10,  6,  9, 11,  5,
+           0, 14,  7, 11, 10,  4, 13,  1,  5,  8, 12,  6,  9,  3,  2, 15,
+          13,  8, 10,  1,  3, 15,  4,  2, 11,  6,  7, 12,  0,  5, 14,  9],
+         [10,  0,  9, 14,  6,  3, 15,  5,  1, 13, 12,  7, 11,  4,  2,  8,
+          13,  7,  0,  9,  3,  4,  6, 10,  2,  8,  5, 14, 12, 11, 15,  1,
+          13,  6,  4,  9,  8, 15,  3,  0, 11,  1,  2, 12,  5, 10, 14,  7,
+           1, 10, 13,  0,  6,  9,  8,  7,  4, 15, 14,  3, 11,  5,  2, 12],
+         [ 7, 13, 14,  3,  0,  6,  9, 10,  1,  2,  8,  5, 11, 12,  4, 15,
+          13,  8, 11,  5,  6, 15,  0,  3,  4,  7,  2, 12,  1, 10, 14,  9,
+          10,  6,  9,  0, 12, 11,  7, 13, 15,  1,  3, 14,  5,  2,  8,  4,
+           3, 15,  0,  6, 10,  1, 13,  8,  9,  4,  5, 11, 12,  7,  2, 14],
+         [ 2, 12,  4,  1,  7, 10, 11,  6,  8,  5,  3, 15, 13,  0, 14,  9,
+          14, 11,  2, 12,  4,  7, 13,  1,  5,  0, 15, 10,  3,  9,  8,  6,
+           4,  2,  1, 11, 10, 13,  7,  8, 15,  9, 12,  5,  6,  3,  0, 14,
+          11,  8, 12,  7,  1, 14,  2, 13,  6, 15,  0,  9, 10,  4,  5,  3],
+         [12,  1, 10, 15,  9,  2,  6,  8,  0, 13,  3,  4, 14,  7,  5, 11,
+          10, 15,  4,  2,  7, 12,  9,  5,  6,  1, 13, 14,  0, 11,  3,  8,
+           9, 14, 15,  5,  2,  8, 12,  3,  7,  0,  4, 10,  1, 13, 11,  6,
+           4,  3,  2, 12,  9,  5, 15, 10, 11, 14,  1,  7,  6,  0,  8, 13],
+         [ 4, 11,  2, 14, 15,  0,  8, 13,  3, 12,  9,  7,  5, 10,  6,  1,
+          13,  0, 11,  7,  4,  9,  1, 10, 14,  3,  5, 12,  2, 15,  8,  6,
+           1,  4, 11, 13, 12,  3,  7, 14, 10, 15,  6,  8,  0,  5,  9,  2,
+           6, 11, 13,  8,  1,  4, 10,  7,  9,  5,  0, 15, 14,  2,  3, 12],
+         [13,  2,  8,  4,  6, 15, 11,  1, 10,  9,  3, 14,  5,  0, 12,  7,
+           1, 15, 13,  8, 10,  3,  7,  4, 12,  5,  6, 11,  0, 14,  9,  2,
+           7, 11,  4,  1,  9, 12, 14,  2,  0,  6, 10, 13, 15,  3,  5,  8,
+           2,  1, 14,  7,  4, 10,  8, 13, 15, 12,  9,  0,  3,  5,  6, 11]];
+
+    // Pick out the bits of input named by the table, where input is
+    // in_bits wide
+    fn permute(input: u64, in_bits: uint, table: &[u8]) -> u64 {
+        table.iter().fold(0u64, |out, bit| (out << 1) | ((input >> (in_bits - *bit as uint)) & 1))
+    }
+
+    fn read_block(block: &[u8]) -> u64 {
+        block.iter().fold(0u64, |word, b| (word << 8) | *b as u64)
+    }
+
+    fn write_block(word: u64, block: &mut [u8]) {
+        for i in range(0u,8) {
+            block[i] = (word >> (56 - 8*i)) as u8;
+        }
+    }
+
+    // The round function: expand the right half to 48 bits, mix in the
+    // subkey, squeeze it back to 32 bits through the S-boxes and permute
+    fn feistel(right: u64, subkey: u64) -> u64 {
+        let mixed = permute(right, 32, E) ^ subkey;
+        let mut substituted = 0u64;
+        for i in range(0u,8)
         {
-            let block_start = block_no * block_size;
+            let six = ((mixed >> (42 - 6*i)) & 0x3f) as uint;
+            let row = ((six >> 4) & 2) | (six & 1);
+            let column = (six >> 1) & 0xf;
+            substituted = (substituted << 4) | SBOXES[i][16*row + column] as u64;
+        }
+        permute(substituted, 32, P)
+    }
 
-            // XOR with previous ciphertext block to turn it into CBC
-            let xor_block = super::xor::repeat_key_xor(
-                prev_cipherblock[],
-                preproc.slice(block_start, block_start + block_size));
+    #[deriving(Clone)]
+    pub struct Des {
+        subkeys: Vec<u64>
+    }
 
-            // ECB encrypt
-            let ecb_block = openssl::crypto::symm::encrypt(
-                openssl::crypto::symm::AES_128_ECB,
-                key,
-                Vec::new(),
-                xor_block[]);
+    impl Des {
+        // The key is 8 bytes, with the low bit of each byte a parity bit
+        // that is ignored
+        pub fn new(key: &[u8]) -> Result<Des, InvalidKeyLength> {
+            if key.len() != 8 {
+                return Err(InvalidKeyLength(key.len()));
+            }
 
-            // Store the single decrypted block, but skip any padding which
-            // may have been added by ecb mode openssl
-            let encrypted_block = ecb_block.slice(0,block_size);
+            let halves = permute(read_block(key), 64, PC1);
+            let mut c = halves >> 28;
+            let mut d = halves & 0xfffffff;
+            let mut subkeys: Vec<u64> = Vec::with_capacity(16);
+            for shift in SHIFTS.iter()
+            {
+                c = ((c << *shift) | (c >> (28 - *shift))) & 0xfffffff;
+                d = ((d << *shift) | (d >> (28 - *shift))) & 0xfffffff;
+                subkeys.push(permute((c << 28) | d, 56, PC2));
+            }
+            Ok(Des { subkeys: subkeys })
+        }
 
-            // store the cipherblock for the next xor
-            prev_cipherblock = encrypted_block.to_vec();
+        // Decryption is the same network with the subkeys in reverse
+        fn crypt_block(&self, block: &mut [u8], decrypt: bool) {
+            let permuted = permute(read_block(block), 64, IP);
+            let mut left = permuted >> 32;
+            let mut right = permuted & 0xffffffff;
+            for round in range(0u,16)
+            {
+                let subkey = if decrypt { self.subkeys[15 - round] } else { self.subkeys[round] };
+                let next_right = left ^ feistel(right, subkey);
+                left = right;
+                right = next_right;
+            }
+            write_block(permute((right << 32) | left, 64, FP), block);
+        }
+    }
 
-            encrypted.push_all(encrypted_block);
+    impl BlockCipher for Des {
+        fn block_size(&self) -> uint {
+            BLOCK_SIZE
+        }
+
+        fn encrypt_block(&self, block: &mut [u8]) {
+            assert_eq!(block.len(), BLOCK_SIZE);
+            self.crypt_block(block, false);
+        }
+
+        fn decrypt_block(&self, block: &mut [u8]) {
+            assert_eq!(block.len(), BLOCK_SIZE);
+            self.crypt_block(block, true);
         }
-        encrypted
     }
 
-    pub fn ecb_decrypt(key:&[u8], data: &[u8], iv:&[u8]) -> Vec<u8> {
-        // match signature of the cbc version
-        openssl::crypto::symm::decrypt(
-            openssl::crypto::symm::AES_128_ECB,
-            key,
-            iv.to_vec(),
-            data)
+    // Triple DES as encrypt-decrypt-encrypt. A 16 byte key is two key EDE2,
+    // where the first key is used again at the end, and a 24 byte key is
+    // three key EDE3.
+    #[deriving(Clone)]
+    pub struct TripleDes {
+        first: Des,
+        second: Des,
+        third: Des
     }
 
-    pub fn ecb_encrypt(key:&[u8], data: &[u8], iv:&[u8]) -> Vec<u8> {
-        // match signature of the cbc version
-        openssl::crypto::symm::encrypt(
-            openssl::crypto::symm::AES_128_ECB,
-            key,
-            iv.to_vec(),
-            data)
+    impl TripleDes {
+        pub fn new(key: &[u8]) -> Result<TripleDes, InvalidKeyLength> {
+            let third_key = match key.len() {
+                16 => key.slice(0, 8),
+                24 => key.slice(16, 24),
+                other => return Err(InvalidKeyLength(other))
+            };
+            Ok(TripleDes {
+                first: Des::new(key.slice(0, 8)).unwrap(),
+                second: Des::new(key.slice(8, 16)).unwrap(),
+                third: Des::new(third_key).unwrap()
+            })
+        }
+    }
+
+    impl BlockCipher for TripleDes {
+        fn block_size(&self) -> uint {
+            BLOCK_SIZE
+        }
+
+        fn encrypt_block(&self, block: &mut [u8]) {
+            self.first.encrypt_block(block);
+            self.second.decrypt_block(block);
+            self.third.encrypt_block(block);
+        }
+
+        fn decrypt_block(&self, block: &mut [u8]) {
+            self.third.decrypt_block(block);
+            self.second.encrypt_block(block);
+            self.first.decrypt_block(block);
+        }
     }
 
-    pub fn uses_ecb_mode(crypter: |msg:&[u8]| -> Vec<u8>) -> bool
+    #[test]
+    fn test_des()
     {
-        // Construct a message that spans three complete blocks.
-        // This means that at least two complete blocks will be
-        // occupied by the message no matter how it is padded.
-        let msg = Vec::from_elem(16*3, 'A' as u8);
-        let ciphertext = crypter(msg[]);
-        super::blocks::has_duplicate_blocks(16,ciphertext[])
+        use serialize::hex::FromHex;
+
+        let cipher = Des::new("133457799bbcdff1".from_hex().unwrap()[]).unwrap();
+        let mut block = "0123456789abcdef".from_hex().unwrap();
+        cipher.encrypt_block(block.as_mut_slice());
+        assert_eq!("85e813540f0ab405".from_hex().unwrap(), block);
+        cipher.decrypt_block(block.as_mut_slice());
+        assert_eq!("0123456789abcdef".from_hex().unwrap(), block);
+
+        let cipher = Des::new("0123456789abcdef".from_hex().unwrap()[]).unwrap();
+        let mut block = "Now is t".as_bytes().to_vec();
+        cipher.encrypt_block(block.as_mut_slice());
+        assert_eq!("3fa40e8a984d4815".from_hex().unwrap(), block);
+
+        // Parity bits make no difference
+        let flipped = Des::new("0022446688aaccee".from_hex().unwrap()[]).unwrap();
+        let unflipped = Des::new("0123456789abcdef".from_hex().unwrap()[]).unwrap();
+        let mut a = [7u8, ..8];
+        let mut b = [7u8, ..8];
+        flipped.encrypt_block(a.as_mut_slice());
+        unflipped.encrypt_block(b.as_mut_slice());
+        assert_eq!(a, b);
+
+        assert_eq!(Some(InvalidKeyLength(7)), Des::new([0u8, ..7]).err());
     }
-    
 
     #[test]
-    fn test_cbc_mode()
+    fn test_triple_des()
     {
-        let msg = "The cake is a lie, the cake is a lie, THE CAKE IS A LIE!".as_bytes();
-        let key = "yellow submarine".as_bytes();
+        use serialize::hex::FromHex;
+        use super::crypto::{cbc_encrypt_blocks, cbc_decrypt_with, ecb_encrypt_blocks};
 
-        let iv1 = [0u8, ..16];
-        let ciphertext1 = cbc_encrypt(key,msg,iv1);
-        let plaintext1  = cbc_decrypt(key,ciphertext1[],iv1);
+        let msg = "The quick brown fox jump".as_bytes();
+        let ede2 = TripleDes::new("0123456789abcdeffedcba9876543210".from_hex().unwrap()[]).unwrap();
+        assert_eq!("ae904afd69fe1c514be1efd932e34fac4bbc5fdd3ab5e1b2".from_hex().unwrap(),
+                   ecb_encrypt_blocks(&ede2, msg));
 
-        let iv2 = [8u8, ..16];
-        let ciphertext2 = cbc_encrypt(key,msg,iv2);
-        let plaintext2  = cbc_decrypt(key,ciphertext2[],iv2);
+        let key3 = "0123456789abcdeffedcba987654321089abcdef01234567".from_hex().unwrap();
+        let ede3 = TripleDes::new(key3[]).unwrap();
+        assert_eq!("5de3127f7a416b4db4d2e7fe238e09eb1fc955bfe20eb75c".from_hex().unwrap(),
+                   ecb_encrypt_blocks(&ede3, msg));
 
-        assert!(ciphertext1 != ciphertext2);
-        assert_eq!(msg,plaintext1[]);
-        assert_eq!(msg,plaintext2[]);
+        let iv = "1234567890abcdef".from_hex().unwrap();
+        let ciphertext = cbc_encrypt_blocks(&ede3, "Now is the time for all ".as_bytes(), iv[]);
+        assert_eq!("204011f986e35647199e47af391620c5bb9a5bcfc86db0bb".from_hex().unwrap(), ciphertext);
+
+        // With all three keys the same it is just single DES
+        let single = Des::new(key3.slice_to(8)).unwrap();
+        let mut repeated = key3.slice_to(8).to_vec();
+        repeated.push_all(key3.slice_to(8));
+        repeated.push_all(key3.slice_to(8));
+        let degenerate = TripleDes::new(repeated[]).unwrap();
+        assert_eq!(ecb_encrypt_blocks(&single, msg), ecb_encrypt_blocks(&degenerate, msg));
+
+        let padded = super::crypto::cbc_encrypt_with(&ede2, "short".as_bytes(), iv[]);
+        assert_eq!(8, padded.len());
+        assert_eq!("short".as_bytes(), cbc_decrypt_with(&ede2, padded[], iv[]).unwrap()[]);
+        assert_eq!(Some(InvalidKeyLength(8)), TripleDes::new(key3.slice_to(8)).err());
     }
 
-    pub fn ecb_suffix_decrypter(oracle: |msg: &[u8]| -> Vec<u8>) -> Vec<u8> {
-        let (block_size,plaintext_len) = super::blocks::analyze_oracle(|msg|oracle(msg));
+    #[test]
+    fn test_ecb_attacks_with_des()
+    {
+        use super::crypto::{cbc_encrypt_with, ecb_encrypt_with, ecb_suffix_decrypter, uses_ecb_mode};
+
+        let cipher = TripleDes::new("Sixteen byte key".as_bytes()).unwrap();
+        let suffix = "Legacy payment data: PAN 4111111111111111".as_bytes();
+        let iv = [0u8, ..8];
 
-        let prefix_char = 'A' as u8;
-        let mut plaintext = Vec::<u8>::new();
+        assert_eq!(true, uses_ecb_mode(BLOCK_SIZE, |msg| ecb_encrypt_with(&cipher, msg)));
+        assert_eq!(false, uses_ecb_mode(BLOCK_SIZE, |msg| cbc_encrypt_with(&cipher, msg, iv)));
 
-        for byte_no in range(0u,plaintext_len) {
-            let block_no = byte_no / block_size;
-            let mut prefix = Vec::from_elem(
-                block_size - 1 - (byte_no%block_size), // pad the char up to end of block
-                prefix_char);
+        let (block_size, suffix_len) = super::blocks::analyze_oracle(|msg: &[u8]| {
+            let mut plaintext = msg.to_vec();
+            plaintext.push_all(suffix);
+            ecb_encrypt_with(&cipher, plaintext[])
+        });
+        assert_eq!(BLOCK_SIZE, block_size);
+        assert_eq!(suffix.len(), suffix_len);
 
-            let prefix_ciphertext = oracle(prefix[]);
-            let prefix_target = prefix_ciphertext.slice(0,(block_no+1)*block_size);
+        let result = ecb_suffix_decrypter('A' as u8, |msg| {
+            let mut plaintext = msg.to_vec();
+            plaintext.push_all(suffix);
+            ecb_encrypt_with(&cipher, plaintext[])
+        });
+        assert_eq!(suffix, result.plaintext[]);
+    }
+}
+
+pub mod crypto {
+    use std::cmp;
+    use std::default::Default;
+    use std::rand;
+    use super::aes::Aes;
+    use super::pad::{PaddingError, Pkcs7Padding};
 
-            prefix.push_all(plaintext[]);
-            prefix.push(0); // add one to be mutated
-            let check_index = prefix.len() - 1;
-            for check_ordinal in range(0u,256) {
-                let check_byte = check_ordinal as u8;
-                prefix[check_index] = check_byte;
+    // Anything that can encrypt and decrypt single fixed size blocks in place
+    pub trait BlockCipher {
+        fn block_size(&self) -> uint;
+        fn encrypt_block(&self, block: &mut [u8]);
+        fn decrypt_block(&self, block: &mut [u8]);
+    }
 
-                let check_ciphertext = oracle(prefix[]);
-                let check_target = check_ciphertext.slice(0,(block_no+1)*block_size);
+    // What a block cipher's constructor returns for a key of the wrong size
+    #[deriving(Show, PartialEq)]
+    pub struct InvalidKeyLength(pub uint);
 
-                if prefix_target == check_target {
-                    // found the byte
-                    plaintext.push(check_byte);
-                    continue;
-                }
-            }
+    // A borrowed cipher works as well, so modes can share one key schedule
+    impl<'a, C: BlockCipher> BlockCipher for &'a C {
+        fn block_size(&self) -> uint { (**self).block_size() }
+        fn encrypt_block(&self, block: &mut [u8]) { (**self).encrypt_block(block) }
+        fn decrypt_block(&self, block: &mut [u8]) { (**self).decrypt_block(block) }
+    }
+
+    // What the authenticated modes return when a tag doesn't match
+    #[deriving(Show, PartialEq)]
+    pub struct BadTag;
+
+    // Compare a computed tag with a received one without stopping at the
+    // first difference, so the time taken doesn't say how much matched
+    pub fn tags_match(expected: &[u8], tag: &[u8]) -> bool {
+        if expected.len() != tag.len() {
+            return false;
         }
-        plaintext
+        let mut difference = 0u8;
+        for (a, b) in expected.iter().zip(tag.iter()) {
+            difference |= *a ^ *b;
+        }
+        difference == 0
+    }
+
+    // What the checked AES shorthands can fail with
+    #[deriving(Show, PartialEq, Clone)]
+    pub enum CryptoError {
+        InvalidKeyLength(uint),
+        Padding(PaddingError)
+    }
+
+    // ECB over whole blocks, without any padding
+    pub fn ecb_encrypt_blocks<C: BlockCipher>(cipher: &C, data: &[u8]) -> Vec<u8> {
+        let block_size = cipher.block_size();
+        assert_eq!(data.len() % block_size, 0);
+
+        let mut encrypted = data.to_vec();
+        for block in encrypted.as_mut_slice().chunks_mut(block_size)
+        {
+            cipher.encrypt_block(block);
+        }
+        encrypted
+    }
+
+    pub fn ecb_decrypt_blocks<C: BlockCipher>(cipher: &C, data: &[u8]) -> Vec<u8> {
+        let block_size = cipher.block_size();
+        assert_eq!(data.len() % block_size, 0);
+
+        let mut decrypted = data.to_vec();
+        for block in decrypted.as_mut_slice().chunks_mut(block_size)
+        {
+            cipher.decrypt_block(block);
+        }
+        decrypted
     }
 
+    // CBC over whole blocks, without any padding
+    pub fn cbc_encrypt_blocks<C: BlockCipher>(cipher: &C, data: &[u8], iv: &[u8]) -> Vec<u8> {
+        let block_size = cipher.block_size();
+        assert_eq!(iv.len(), block_size);
+        assert_eq!(data.len() % block_size, 0);
+
+        let mut encrypted: Vec<u8> = Vec::new();
+        let mut prev_cipherblock = iv.to_vec();
+        for plainblock in data.chunks(block_size)
+        {
+            // XOR with previous ciphertext block to turn it into CBC
+            let mut encrypted_block = super::xor::repeat_key_xor(
+                prev_cipherblock[],
+                plainblock);
+
+            cipher.encrypt_block(encrypted_block.as_mut_slice());
+            encrypted.push_all(encrypted_block[]);
+
+            // store the cipherblock for the next xor
+            prev_cipherblock = encrypted_block;
+        }
+        encrypted
+    }
+
+    pub fn cbc_decrypt_blocks<C: BlockCipher>(cipher: &C, data: &[u8], iv: &[u8]) -> Vec<u8> {
+        let block_size = cipher.block_size();
+        assert_eq!(iv.len(), block_size);
+        assert_eq!(data.len() % block_size, 0);
+
+        let mut decrypted: Vec<u8> = Vec::new();
+        let mut prev_cipherblock = iv;
+        for cipherblock in data.chunks(block_size)
+        {
+            let mut decrypted_block = cipherblock.to_vec();
+            cipher.decrypt_block(decrypted_block.as_mut_slice());
+
+            // XOR with previous ciphertext block to turn it into CBC
+            decrypted.push_all(super::xor::repeat_key_xor(
+                prev_cipherblock,
+                decrypted_block[])[]);
+
+            prev_cipherblock = cipherblock;
+        }
+        decrypted
+    }
+
+    // PCBC over whole blocks, without any padding. Each block is chained
+    // with both the previous plaintext and ciphertext blocks, so a change
+    // anywhere garbles everything after it when decrypting.
+    pub fn pcbc_encrypt_blocks<C: BlockCipher>(cipher: &C, data: &[u8], iv: &[u8]) -> Vec<u8> {
+        let block_size = cipher.block_size();
+        assert_eq!(iv.len(), block_size);
+        assert_eq!(data.len() % block_size, 0);
+
+        let mut encrypted: Vec<u8> = Vec::new();
+        let mut chain = iv.to_vec();
+        for plainblock in data.chunks(block_size)
+        {
+            let mut encrypted_block = super::xor::repeat_key_xor(chain[], plainblock);
+            cipher.encrypt_block(encrypted_block.as_mut_slice());
+            encrypted.push_all(encrypted_block[]);
+
+            // the next block is chained with plaintext xor ciphertext
+            chain = super::xor::repeat_key_xor(plainblock, encrypted_block[]);
+        }
+        encrypted
+    }
+
+    pub fn pcbc_decrypt_blocks<C: BlockCipher>(cipher: &C, data: &[u8], iv: &[u8]) -> Vec<u8> {
+        let block_size = cipher.block_size();
+        assert_eq!(iv.len(), block_size);
+        assert_eq!(data.len() % block_size, 0);
+
+        let mut decrypted: Vec<u8> = Vec::new();
+        let mut chain = iv.to_vec();
+        for cipherblock in data.chunks(block_size)
+        {
+            let mut decrypted_block = cipherblock.to_vec();
+            cipher.decrypt_block(decrypted_block.as_mut_slice());
+            let plainblock = super::xor::repeat_key_xor(chain[], decrypted_block[]);
+            decrypted.push_all(plainblock[]);
+
+            chain = super::xor::repeat_key_xor(plainblock[], cipherblock);
+        }
+        decrypted
+    }
+
+    // Padded ECB and CBC for any block cipher
+    pub fn ecb_encrypt_with<C: BlockCipher>(cipher: &C, data: &[u8]) -> Vec<u8> {
+        let mut padded = data.to_vec();
+        padded.pkcs7_extend(cipher.block_size());
+        ecb_encrypt_blocks(cipher, padded[])
+    }
+
+    pub fn ecb_decrypt_with<C: BlockCipher>(cipher: &C, data: &[u8]) -> Result<Vec<u8>, PaddingError> {
+        let block_size = cipher.block_size();
+        if data.len() % block_size != 0 {
+            return Err(PaddingError::BadLength(data.len()));
+        }
+        let mut decrypted = ecb_decrypt_blocks(cipher, data);
+        try!(decrypted.pkcs7_trim(block_size));
+        Ok(decrypted)
+    }
+
+    pub fn cbc_encrypt_with<C: BlockCipher>(cipher: &C, data: &[u8], iv: &[u8]) -> Vec<u8> {
+        let mut padded = data.to_vec();
+        padded.pkcs7_extend(cipher.block_size());
+        cbc_encrypt_blocks(cipher, padded[], iv)
+    }
+
+    pub fn cbc_decrypt_with<C: BlockCipher>(cipher: &C, data: &[u8], iv: &[u8]) -> Result<Vec<u8>, PaddingError> {
+        let block_size = cipher.block_size();
+        if data.len() % block_size != 0 {
+            return Err(PaddingError::BadLength(data.len()));
+        }
+        let mut decrypted = cbc_decrypt_blocks(cipher, data, iv);
+        try!(decrypted.pkcs7_trim(block_size));
+        Ok(decrypted)
+    }
+
+    pub fn pcbc_encrypt_with<C: BlockCipher>(cipher: &C, data: &[u8], iv: &[u8]) -> Vec<u8> {
+        let mut padded = data.to_vec();
+        padded.pkcs7_extend(cipher.block_size());
+        pcbc_encrypt_blocks(cipher, padded[], iv)
+    }
+
+    pub fn pcbc_decrypt_with<C: BlockCipher>(cipher: &C, data: &[u8], iv: &[u8]) -> Result<Vec<u8>, PaddingError> {
+        let block_size = cipher.block_size();
+        if data.len() % block_size != 0 {
+            return Err(PaddingError::BadLength(data.len()));
+        }
+        let mut decrypted = pcbc_decrypt_blocks(cipher, data, iv);
+        try!(decrypted.pkcs7_trim(block_size));
+        Ok(decrypted)
+    }
+
+    // Ciphertext stealing variants from the NIST SP 800-38A addendum. They
+    // differ only in the order of the last two ciphertext blocks: CS1
+    // keeps CBC order, CS3 always swaps them (as in Kerberos) and CS2
+    // swaps them only when the last block is partial.
+    #[deriving(Clone, Show, PartialEq)]
+    pub enum CtsVariant {
+        Cs1,
+        Cs2,
+        Cs3
+    }
+
+    fn cts_swaps_last_blocks(variant: &CtsVariant, last_len: uint, block_size: uint) -> bool {
+        match *variant {
+            CtsVariant::Cs1 => false,
+            CtsVariant::Cs2 => last_len != block_size,
+            CtsVariant::Cs3 => true
+        }
+    }
+
+    // CBC with ciphertext stealing, where the ciphertext is exactly as
+    // long as the plaintext. The last plaintext block is zero padded, and
+    // the second to last ciphertext block gives up the bytes that padding
+    // took. The data has to be at least one block long.
+    pub fn cbc_cts_encrypt_with<C: BlockCipher>(cipher: &C, variant: CtsVariant, data: &[u8], iv: &[u8]) -> Vec<u8> {
+        let block_size = cipher.block_size();
+        assert!(data.len() >= block_size);
+
+        let num_blocks = (data.len() + block_size - 1) / block_size;
+        let last_len = data.len() - (num_blocks - 1)*block_size;
+        let mut padded = data.to_vec();
+        padded.grow(num_blocks*block_size - data.len(), 0);
+        let encrypted = cbc_encrypt_blocks(cipher, padded[], iv);
+        if num_blocks == 1 {
+            return encrypted;
+        }
+
+        let stolen = super::blocks::nth_block(block_size, encrypted[], num_blocks - 2).slice_to(last_len);
+        let last = super::blocks::nth_block(block_size, encrypted[], num_blocks - 1);
+        let mut output = encrypted.slice_to((num_blocks - 2)*block_size).to_vec();
+        if cts_swaps_last_blocks(&variant, last_len, block_size) {
+            output.push_all(last);
+            output.push_all(stolen);
+        } else {
+            output.push_all(stolen);
+            output.push_all(last);
+        }
+        output
+    }
+
+    pub fn cbc_cts_decrypt_with<C: BlockCipher>(cipher: &C, variant: CtsVariant, data: &[u8], iv: &[u8]) -> Vec<u8> {
+        let block_size = cipher.block_size();
+        assert!(data.len() >= block_size);
+
+        let num_blocks = (data.len() + block_size - 1) / block_size;
+        let last_len = data.len() - (num_blocks - 1)*block_size;
+        if num_blocks == 1 {
+            return cbc_decrypt_blocks(cipher, data, iv);
+        }
+
+        // Put the stolen bytes and the last block back into CBC order
+        let tail = data.slice_from((num_blocks - 2)*block_size);
+        let (stolen, last) = if cts_swaps_last_blocks(&variant, last_len, block_size) {
+            (tail.slice_from(block_size), tail.slice_to(block_size))
+        } else {
+            (tail.slice_to(last_len), tail.slice_from(last_len))
+        };
+
+        // The last block decrypts to the zero padded plaintext xored with
+        // the whole second to last block, so the bytes that were stolen
+        // from it can be read back off the end
+        let mut decrypted_last = last.to_vec();
+        cipher.decrypt_block(decrypted_last.as_mut_slice());
+        let mut second_to_last = stolen.to_vec();
+        second_to_last.push_all(decrypted_last.slice_from(last_len));
+
+        let mut chained = data.slice_to((num_blocks - 2)*block_size).to_vec();
+        chained.push_all(second_to_last[]);
+        let mut decrypted = cbc_decrypt_blocks(cipher, chained[], iv);
+        decrypted.push_all(super::xor::repeat_key_xor(stolen, decrypted_last.slice_to(last_len))[]);
+        decrypted
+    }
+
+    // XTS (IEEE 1619) encrypts each 16 byte block of a disk sector as
+    // E(k1, P xor T) xor T. The tweak T starts as the sector number
+    // encrypted under k2, and is multiplied by alpha in GF(2^128) for each
+    // block after that. A partial last block steals ciphertext from the
+    // one before it, so sectors keep their length.
+    pub struct Xts<C> {
+        data_cipher: C,
+        tweak_cipher: C
+    }
+
+    impl<C: BlockCipher> Xts<C> {
+        pub fn new(data_cipher: C, tweak_cipher: C) -> Xts<C> {
+            assert_eq!(16, data_cipher.block_size());
+            assert_eq!(16, tweak_cipher.block_size());
+            Xts { data_cipher: data_cipher, tweak_cipher: tweak_cipher }
+        }
+
+        fn tweaks(&self, sector: u64, count: uint) -> Vec<Vec<u8>> {
+            let mut tweak = Vec::from_fn(16, |i| if i < 8 { (sector >> 8*i) as u8 } else { 0 });
+            self.tweak_cipher.encrypt_block(tweak.as_mut_slice());
+
+            let mut tweaks: Vec<Vec<u8>> = Vec::with_capacity(count);
+            for _ in range(0u,count)
+            {
+                tweaks.push(tweak.clone());
+                xts_mul_alpha(tweak.as_mut_slice());
+            }
+            tweaks
+        }
+
+        fn encrypt_block_with(&self, block: &[u8], tweak: &[u8]) -> Vec<u8> {
+            let mut encrypted = super::xor::repeat_key_xor(tweak, block);
+            self.data_cipher.encrypt_block(encrypted.as_mut_slice());
+            super::xor::repeat_key_xor(tweak, encrypted[])
+        }
+
+        fn decrypt_block_with(&self, block: &[u8], tweak: &[u8]) -> Vec<u8> {
+            let mut decrypted = super::xor::repeat_key_xor(tweak, block);
+            self.data_cipher.decrypt_block(decrypted.as_mut_slice());
+            super::xor::repeat_key_xor(tweak, decrypted[])
+        }
+
+        pub fn encrypt_sector(&self, sector: u64, data: &[u8]) -> Vec<u8> {
+            assert!(data.len() >= 16);
+            let full_blocks = data.len() / 16;
+            let partial_len = data.len() % 16;
+            let tweaks = self.tweaks(sector, full_blocks + 1);
+
+            let mut encrypted: Vec<u8> = Vec::with_capacity(data.len());
+            for (block, tweak) in data.chunks(16).zip(tweaks.iter()).take(full_blocks)
+            {
+                encrypted.push_all(self.encrypt_block_with(block, tweak[])[]);
+            }
+
+            if partial_len > 0 {
+                // The last full block's ciphertext gives its tail to pad out
+                // the partial block, then moves to the end cut short
+                let stolen_from = encrypted.slice_from((full_blocks - 1)*16).to_vec();
+                encrypted.truncate((full_blocks - 1)*16);
+                let mut last = data.slice_from(full_blocks*16).to_vec();
+                last.push_all(stolen_from.slice_from(partial_len));
+                encrypted.push_all(self.encrypt_block_with(last[], tweaks[full_blocks][])[]);
+                encrypted.push_all(stolen_from.slice_to(partial_len));
+            }
+            encrypted
+        }
+
+        pub fn decrypt_sector(&self, sector: u64, data: &[u8]) -> Vec<u8> {
+            assert!(data.len() >= 16);
+            let full_blocks = data.len() / 16;
+            let partial_len = data.len() % 16;
+            let tweaks = self.tweaks(sector, full_blocks + 1);
+            let in_order = if partial_len > 0 { full_blocks - 1 } else { full_blocks };
+
+            let mut decrypted: Vec<u8> = Vec::with_capacity(data.len());
+            for (block, tweak) in data.chunks(16).zip(tweaks.iter()).take(in_order)
+            {
+                decrypted.push_all(self.decrypt_block_with(block, tweak[])[]);
+            }
+
+            if partial_len > 0 {
+                let last_full = super::blocks::nth_block(16, data, full_blocks - 1);
+                let stolen_from = self.decrypt_block_with(last_full, tweaks[full_blocks][]);
+                let mut second_to_last = data.slice_from(full_blocks*16).to_vec();
+                second_to_last.push_all(stolen_from.slice_from(partial_len));
+                decrypted.push_all(self.decrypt_block_with(second_to_last[], tweaks[full_blocks - 1][])[]);
+                decrypted.push_all(stolen_from.slice_to(partial_len));
+            }
+            decrypted
+        }
+    }
+
+    // Multiply a tweak by alpha, with the tweak as a little endian number
+    fn xts_mul_alpha(tweak: &mut [u8]) {
+        let mut carry = 0u8;
+        for byte in tweak.iter_mut()
+        {
+            let next_carry = *byte >> 7;
+            *byte = (*byte << 1) | carry;
+            carry = next_carry;
+        }
+        if carry == 1 {
+            tweak[0] ^= 0x87;
+        }
+    }
+
+    // XTS-AES shorthands, where the key is the data key followed by the
+    // tweak key: 32 bytes for XTS-AES-128 or 64 for XTS-AES-256. A bad key
+    // panics; build an Xts from aes_for_key to get it as an error.
+    fn xts_for_key(key: &[u8]) -> Xts<Aes> {
+        assert!(key.len() % 2 == 0);
+        let half = key.len() / 2;
+        Xts::new(aes_for_key(key.slice_to(half)).unwrap(), aes_for_key(key.slice_from(half)).unwrap())
+    }
+
+    pub fn xts_encrypt(key: &[u8], sector: u64, data: &[u8]) -> Vec<u8> {
+        xts_for_key(key).encrypt_sector(sector, data)
+    }
+
+    pub fn xts_decrypt(key: &[u8], sector: u64, data: &[u8]) -> Vec<u8> {
+        xts_for_key(key).decrypt_sector(sector, data)
+    }
+
+    // AES shorthands for the above. The key length picks AES-128, AES-192
+    // or AES-256. The checked functions return a bad key or bad padding as
+    // a CryptoError, the plain ones panic on either.
+    pub fn cbc_decrypt(key:&[u8], data: &[u8], iv:&[u8]) -> Vec<u8> {
+        cbc_decrypt_checked(key, data, iv).unwrap()
+    }
+
+    pub fn cbc_decrypt_checked(key:&[u8], data: &[u8], iv:&[u8]) -> Result<Vec<u8>, CryptoError> {
+        let cipher = try!(aes_for_key(key));
+        cbc_decrypt_with(&cipher, data, iv).map_err(CryptoError::Padding)
+    }
+
+    pub fn cbc_encrypt(key:&[u8], data: &[u8], iv:&[u8]) -> Vec<u8> {
+        cbc_encrypt_checked(key, data, iv).unwrap()
+    }
+
+    pub fn cbc_encrypt_checked(key:&[u8], data: &[u8], iv:&[u8]) -> Result<Vec<u8>, CryptoError> {
+        let cipher = try!(aes_for_key(key));
+        Ok(cbc_encrypt_with(&cipher, data, iv))
+    }
+
+    pub fn pcbc_decrypt(key:&[u8], data: &[u8], iv:&[u8]) -> Vec<u8> {
+        pcbc_decrypt_checked(key, data, iv).unwrap()
+    }
+
+    pub fn pcbc_decrypt_checked(key:&[u8], data: &[u8], iv:&[u8]) -> Result<Vec<u8>, CryptoError> {
+        let cipher = try!(aes_for_key(key));
+        pcbc_decrypt_with(&cipher, data, iv).map_err(CryptoError::Padding)
+    }
+
+    pub fn pcbc_encrypt(key:&[u8], data: &[u8], iv:&[u8]) -> Vec<u8> {
+        pcbc_encrypt_checked(key, data, iv).unwrap()
+    }
+
+    pub fn pcbc_encrypt_checked(key:&[u8], data: &[u8], iv:&[u8]) -> Result<Vec<u8>, CryptoError> {
+        let cipher = try!(aes_for_key(key));
+        Ok(pcbc_encrypt_with(&cipher, data, iv))
+    }
+
+    // ECB has no use for an iv, but takes one to match the cbc signature
+    pub fn ecb_decrypt(key:&[u8], data: &[u8], iv:&[u8]) -> Vec<u8> {
+        ecb_decrypt_checked(key, data, iv).unwrap()
+    }
+
+    pub fn ecb_decrypt_checked(key:&[u8], data: &[u8], _iv:&[u8]) -> Result<Vec<u8>, CryptoError> {
+        let cipher = try!(aes_for_key(key));
+        ecb_decrypt_with(&cipher, data).map_err(CryptoError::Padding)
+    }
+
+    pub fn ecb_encrypt(key:&[u8], data: &[u8], iv:&[u8]) -> Vec<u8> {
+        ecb_encrypt_checked(key, data, iv).unwrap()
+    }
+
+    pub fn ecb_encrypt_checked(key:&[u8], data: &[u8], _iv:&[u8]) -> Result<Vec<u8>, CryptoError> {
+        let cipher = try!(aes_for_key(key));
+        Ok(ecb_encrypt_with(&cipher, data))
+    }
+
+    pub fn aes_for_key(key: &[u8]) -> Result<Aes, CryptoError> {
+        match Aes::new(key) {
+            Ok(cipher) => Ok(cipher),
+            Err(InvalidKeyLength(len)) => Err(CryptoError::InvalidKeyLength(len))
+        }
+    }
+
+    // OFB turns the block cipher into a keystream by encrypting the iv over
+    // and over, so encryption and decryption are the same operation and
+    // the data needs no padding.
+    pub fn ofb_apply_with<C: BlockCipher>(cipher: &C, data: &[u8], iv: &[u8]) -> Vec<u8> {
+        let block_size = cipher.block_size();
+        assert_eq!(iv.len(), block_size);
+
+        let mut output: Vec<u8> = Vec::with_capacity(data.len());
+        let mut keystream_block = iv.to_vec();
+        for chunk in data.chunks(block_size)
+        {
+            cipher.encrypt_block(keystream_block.as_mut_slice());
+            output.push_all(super::xor::repeat_key_xor(keystream_block.slice_to(chunk.len()), chunk)[]);
+        }
+        output
+    }
+
+    // CFB with a segment of segment_len bytes, from 1 (CFB-8) up to the
+    // block size (CFB-128 for AES). Each segment is xored with the start of
+    // the encrypted shift register, and the ciphertext segment is then
+    // shifted into the register. A short final segment needs no padding.
+    fn cfb_apply_with<C: BlockCipher>(cipher: &C, segment_len: uint, data: &[u8], iv: &[u8], decrypting: bool) -> Vec<u8> {
+        let block_size = cipher.block_size();
+        assert_eq!(iv.len(), block_size);
+        assert!(segment_len > 0 && segment_len <= block_size);
+
+        let mut output: Vec<u8> = Vec::with_capacity(data.len());
+        let mut register = iv.to_vec();
+        for segment in data.chunks(segment_len)
+        {
+            let mut keystream_block = register.clone();
+            cipher.encrypt_block(keystream_block.as_mut_slice());
+            let processed = super::xor::repeat_key_xor(keystream_block.slice_to(segment.len()), segment);
+
+            let ciphertext_segment = if decrypting { segment } else { processed[] };
+            let mut next_register = register.slice_from(segment.len()).to_vec();
+            next_register.push_all(ciphertext_segment);
+            register = next_register;
+
+            output.push_all(processed[]);
+        }
+        output
+    }
+
+    pub fn cfb_encrypt_with<C: BlockCipher>(cipher: &C, segment_len: uint, data: &[u8], iv: &[u8]) -> Vec<u8> {
+        cfb_apply_with(cipher, segment_len, data, iv, false)
+    }
+
+    pub fn cfb_decrypt_with<C: BlockCipher>(cipher: &C, segment_len: uint, data: &[u8], iv: &[u8]) -> Vec<u8> {
+        cfb_apply_with(cipher, segment_len, data, iv, true)
+    }
+
+    // AES shorthands for OFB, CFB-8 and CFB-128. These panic on a bad key;
+    // use aes_for_key with the _with functions to get it as an error.
+    pub fn ofb_encrypt(key:&[u8], data: &[u8], iv:&[u8]) -> Vec<u8> {
+        ofb_apply_with(&aes_for_key(key).unwrap(), data, iv)
+    }
+
+    pub fn ofb_decrypt(key:&[u8], data: &[u8], iv:&[u8]) -> Vec<u8> {
+        ofb_apply_with(&aes_for_key(key).unwrap(), data, iv)
+    }
+
+    pub fn cfb8_encrypt(key:&[u8], data: &[u8], iv:&[u8]) -> Vec<u8> {
+        cfb_encrypt_with(&aes_for_key(key).unwrap(), 1, data, iv)
+    }
+
+    pub fn cfb8_decrypt(key:&[u8], data: &[u8], iv:&[u8]) -> Vec<u8> {
+        cfb_decrypt_with(&aes_for_key(key).unwrap(), 1, data, iv)
+    }
+
+    pub fn cfb128_encrypt(key:&[u8], data: &[u8], iv:&[u8]) -> Vec<u8> {
+        cfb_encrypt_with(&aes_for_key(key).unwrap(), super::aes::BLOCK_SIZE, data, iv)
+    }
+
+    pub fn cfb128_decrypt(key:&[u8], data: &[u8], iv:&[u8]) -> Vec<u8> {
+        cfb_decrypt_with(&aes_for_key(key).unwrap(), super::aes::BLOCK_SIZE, data, iv)
+    }
+
+    #[deriving(Clone, Show, PartialEq)]
+    pub enum Endian {
+        Little,
+        Big
+    }
+
+    // Layout of a CTR counter block: nonce_len bytes of nonce, followed by
+    // a counter_len byte block counter in the given byte order. Anything
+    // left over at the end of the block is zero.
+    #[deriving(Clone, Show, PartialEq)]
+    pub struct CtrLayout {
+        pub nonce_len: uint,
+        pub counter_len: uint,
+        pub endian: Endian
+    }
+
+    impl CtrLayout {
+        // 64 bit nonce, then a 64 bit little endian block count
+        pub fn cryptopals() -> CtrLayout {
+            CtrLayout { nonce_len: 8, counter_len: 8, endian: Endian::Little }
+        }
+
+        // NIST SP 800-38A treats the whole counter block as one big endian
+        // number, so there is no nonce. Use Ctr::from_counter_block to start
+        // from a full initial counter block.
+        pub fn nist() -> CtrLayout {
+            CtrLayout { nonce_len: 0, counter_len: 16, endian: Endian::Big }
+        }
+    }
+
+    impl Default for CtrLayout {
+        fn default() -> CtrLayout {
+            CtrLayout::cryptopals()
+        }
+    }
+
+    pub struct Ctr<C> {
+        cipher: C,
+        layout: CtrLayout,
+        initial_block: Vec<u8>
+    }
+
+    impl<C: BlockCipher> Ctr<C> {
+        // The first counter block is the nonce, then initial_counter in the
+        // counter bytes, then zeros to fill the block
+        pub fn new(cipher: C, layout: CtrLayout, nonce: &[u8], initial_counter: u64) -> Ctr<C> {
+            assert_eq!(nonce.len(), layout.nonce_len);
+            let mut initial_block = nonce.to_vec();
+            for i in range(0u,layout.counter_len)
+            {
+                let shift = match layout.endian {
+                    Endian::Little => 8*i,
+                    Endian::Big    => 8*(layout.counter_len - 1 - i)
+                };
+                initial_block.push(if shift < 64 { (initial_counter >> shift) as u8 } else { 0 });
+            }
+            while initial_block.len() < cipher.block_size() {
+                initial_block.push(0);
+            }
+            Ctr::from_counter_block(cipher, layout, initial_block[])
+        }
+
+        // Start from a whole counter block, such as an SP 800-38A iv
+        pub fn from_counter_block(cipher: C, layout: CtrLayout, initial_block: &[u8]) -> Ctr<C> {
+            assert_eq!(initial_block.len(), cipher.block_size());
+            assert!(layout.nonce_len + layout.counter_len <= cipher.block_size());
+            Ctr {
+                cipher: cipher,
+                layout: layout,
+                initial_block: initial_block.to_vec()
+            }
+        }
+
+        pub fn block_size(&self) -> uint {
+            self.cipher.block_size()
+        }
+
+        // The plaintext counter block for the block_no'th block of keystream:
+        // block_no added to the initial counter, carrying from byte to byte.
+        // The counter wraps around within its counter_len bytes.
+        pub fn counter_block(&self, block_no: u64) -> Vec<u8> {
+            let nonce_len = self.layout.nonce_len;
+            let counter_len = self.layout.counter_len;
+
+            let mut block = self.initial_block.clone();
+            let mut carry = block_no;
+            for i in range(0u,counter_len)
+            {
+                let pos = match self.layout.endian {
+                    Endian::Little => nonce_len + i,
+                    Endian::Big    => nonce_len + counter_len - 1 - i
+                };
+                let sum = block[pos] as u64 + (carry & 0xff);
+                block[pos] = sum as u8;
+                carry = (carry >> 8) + (sum >> 8);
+            }
+            block
+        }
+
+        pub fn keystream_block(&self, block_no: u64) -> Vec<u8> {
+            let mut block = self.counter_block(block_no);
+            self.cipher.encrypt_block(block.as_mut_slice());
+            block
+        }
+
+        // Keystream bytes from offset to offset+len
+        pub fn keystream(&self, offset: u64, len: uint) -> Vec<u8> {
+            let block_size = self.block_size() as u64;
+            let mut keystream: Vec<u8> = Vec::with_capacity(len + block_size as uint);
+            let mut block_no = offset / block_size;
+            let skip = (offset % block_size) as uint;
+
+            while keystream.len() < skip + len {
+                keystream.push_all(self.keystream_block(block_no)[]);
+                block_no += 1;
+            }
+            keystream.slice(skip, skip + len).to_vec()
+        }
+
+        // Encrypts or decrypts data sitting at the given byte offset of the
+        // stream, so offset = n*block_size seeks to block n
+        pub fn apply_at(&self, offset: u64, data: &[u8]) -> Vec<u8> {
+            let keystream = self.keystream(offset, data.len());
+            data.iter().zip(keystream.iter())
+                .map(|(d,k)| *d ^ *k)
+                .collect::<Vec<u8>>()
+        }
+
+        pub fn apply(&self, data: &[u8]) -> Vec<u8> {
+            self.apply_at(0, data)
+        }
+
+        // Replace the plaintext under ciphertext from offset onwards with
+        // newtext, which may run past the end and lengthen the ciphertext
+        pub fn edit(&self, ciphertext: &[u8], offset: uint, newtext: &[u8]) -> Vec<u8> {
+            assert!(offset <= ciphertext.len());
+            let mut edited = ciphertext.slice_to(offset).to_vec();
+            edited.push_all(self.apply_at(offset as u64, newtext)[]);
+            if offset + newtext.len() < ciphertext.len() {
+                edited.push_all(ciphertext.slice_from(offset + newtext.len()));
+            }
+            edited
+        }
+    }
+
+    // AES CTR with the cryptopals layout, counting from zero. As with the
+    // CBC shorthands, the checked ones return a bad key as a CryptoError.
+    pub fn ctr_encrypt(key: &[u8], nonce: &[u8], data: &[u8]) -> Vec<u8> {
+        ctr_encrypt_checked(key, nonce, data).unwrap()
+    }
+
+    pub fn ctr_encrypt_checked(key: &[u8], nonce: &[u8], data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let cipher = try!(aes_for_key(key));
+        Ok(Ctr::new(cipher, Default::default(), nonce, 0).apply(data))
+    }
+
+    pub fn ctr_decrypt(key: &[u8], nonce: &[u8], data: &[u8]) -> Vec<u8> {
+        ctr_encrypt(key, nonce, data)
+    }
+
+    pub fn ctr_decrypt_checked(key: &[u8], nonce: &[u8], data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        ctr_encrypt_checked(key, nonce, data)
+    }
+
+    pub fn ctr_edit(ciphertext: &[u8], key: &[u8], nonce: &[u8], offset: uint, newtext: &[u8]) -> Vec<u8> {
+        Ctr::new(aes_for_key(key).unwrap(), Default::default(), nonce, 0).edit(ciphertext, offset, newtext)
+    }
+
+    // Recover CTR plaintext through an edit function which re-encrypts
+    // part of the ciphertext with our text. Editing in zeroes hands us the
+    // keystream. The edits are made max_edit_len bytes at a time, for
+    // systems which limit how much can be changed at once.
+    pub fn recover_ctr_plaintext_via_edit(ciphertext: &[u8],
+                                          max_edit_len: uint,
+                                          edit: |offset: uint, newtext: &[u8]| -> Vec<u8>) -> Vec<u8> {
+        assert!(max_edit_len > 0);
+        let mut plaintext: Vec<u8> = Vec::with_capacity(ciphertext.len());
+        let mut offset = 0u;
+        while offset < ciphertext.len()
+        {
+            let len = cmp::min(max_edit_len, ciphertext.len() - offset);
+            let edited = edit(offset, Vec::from_elem(len, 0u8)[]);
+            plaintext.push_all(super::xor::repeat_key_xor(
+                edited.slice(offset, offset + len),
+                ciphertext.slice(offset, offset + len))[]);
+            offset += len;
+        }
+        plaintext
+    }
+
+    #[test]
+    fn test_ctr_mode()
+    {
+        use serialize::hex::FromHex;
+
+        // NIST SP 800-38A F.5.1, with a counter block of f0f1...feff
+        let key = "2b7e151628aed2a6abf7158809cf4f3c".from_hex().unwrap();
+        let ctr = Ctr::from_counter_block(
+            Aes::new(key[]).unwrap(),
+            CtrLayout::nist(),
+            "f0f1f2f3f4f5f6f7f8f9fafbfcfdfeff".from_hex().unwrap()[]);
+        let plaintext = "6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e5130c81c46a35ce411e5fbc1191a0a52eff69f2445df4f9b17ad2b417be66c3710"
+            .from_hex()
+            .unwrap();
+        let ciphertext = "874d6191b620e3261bef6864990db6ce9806f66b7970fdff8617187bb9fffdff5ae4df3edbd5d35e5b4f09020db03eab1e031dda2fbe03d1792170a0f3009cee"
+            .from_hex()
+            .unwrap();
+        assert_eq!(ciphertext, ctr.apply(plaintext[]));
+        assert_eq!(plaintext, ctr.apply(ciphertext[]));
+
+        // Seeking to a block, or into the middle of one
+        assert_eq!(ciphertext.slice_from(32), ctr.apply_at(32, plaintext.slice_from(32))[]);
+        assert_eq!(ciphertext.slice(21,50), ctr.apply_at(21, plaintext.slice(21,50))[]);
+
+        // Counter byte order and wrap around
+        let layout = CtrLayout { nonce_len: 4, counter_len: 2, endian: Endian::Little };
+        let ctr = Ctr::new(Aes::new(key[]).unwrap(), layout, [1, 2, 3, 4], 0xfffe);
+        assert_eq!(vec![1, 2, 3, 4, 0xff, 0xff, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                   ctr.counter_block(1));
+        assert_eq!(vec![1, 2, 3, 4, 0x01, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                   ctr.counter_block(3));
+
+        let cryptopals = Ctr::new(Aes::new(key[]).unwrap(), CtrLayout::cryptopals(), [0, ..8], 0);
+        assert_eq!(vec![0, 0, 0, 0, 0, 0, 0, 0, 0x02, 0x01, 0, 0, 0, 0, 0, 0],
+                   cryptopals.counter_block(0x0102));
+
+        // The NIST layout carries out of the low 64 bits into the rest of
+        // the block
+        let iv = "00000000000000fffffffffffffffffe".from_hex().unwrap();
+        let nist = Ctr::from_counter_block(Aes::new(key[]).unwrap(), CtrLayout::nist(), iv[]);
+        assert_eq!("00000000000000ffffffffffffffffff".from_hex().unwrap(), nist.counter_block(1));
+        assert_eq!("00000000000001000000000000000000".from_hex().unwrap(), nist.counter_block(2));
+        assert_eq!("00000000000001000000000000000001".from_hex().unwrap(), nist.counter_block(3));
+        let mut expected = nist.counter_block(2);
+        Aes::new(key[]).unwrap().encrypt_block(expected.as_mut_slice());
+        assert_eq!(expected, nist.keystream(32, 16));
+
+        // and the whole block wraps around to zero
+        let top = Ctr::from_counter_block(Aes::new(key[]).unwrap(), CtrLayout::nist(), [0xffu8, ..16]);
+        assert_eq!(Vec::from_elem(16, 0u8), top.counter_block(1));
+    }
+
+    pub struct FixedNonceBreak {
+        pub keystream: Vec<u8>,
+        pub plaintexts: Vec<Vec<u8>>,
+        // How many ciphertexts reach each keystream position
+        pub samples: Vec<uint>
+    }
+
+    // Break a set of CTR ciphertexts which were all encrypted with the same
+    // key and nonce, and so share one keystream. Cut down to the length of
+    // the shortest, they make one long repeating-key xor which
+    // find_best_repeating_xor_key can break. Past that, each keystream
+    // byte is scored on its own using whichever ciphertexts reach that
+    // far, so the last few bytes rest on very few samples and are little
+    // better than guesses; samples says how much each byte can be trusted.
+    // The first column is mostly capital letters, which an ordinary model
+    // of English mistakes for lower case, so it gets its own scorer.
+    pub fn break_fixed_nonce_ctr(ciphertexts: &[Vec<u8>],
+                                 initial_scorer: |&[u8]| -> f32,
+                                 scorer: |&[u8]| -> f32) -> FixedNonceBreak {
+        assert!(ciphertexts.len() > 0);
+        let min_len = ciphertexts.iter().map(|c| c.len()).min().unwrap();
+        let max_len = ciphertexts.iter().map(|c| c.len()).max().unwrap();
+
+        let mut keystream: Vec<u8> = Vec::with_capacity(max_len);
+        if min_len > 0 {
+            let mut truncated: Vec<u8> = Vec::new();
+            for ciphertext in ciphertexts.iter() {
+                truncated.push_all(ciphertext.slice_to(min_len));
+            }
+            keystream = super::xor::find_best_repeating_xor_key(
+                min_len,
+                truncated[],
+                |text| scorer(text));
+
+            let first_column = ciphertexts.iter().map(|c| c[0]).collect::<Vec<u8>>();
+            let (_,key_byte,_) = super::xor::find_best_xor_key(first_column[], |text| initial_scorer(text));
+            keystream[0] = key_byte;
+        }
+
+        for pos in range(min_len,max_len)
+        {
+            let column = ciphertexts
+                .iter()
+                .filter(|c| c.len() > pos)
+                .map(|c| c[pos])
+                .collect::<Vec<u8>>();
+            let (_,key_byte,_) = super::xor::find_best_xor_key(column[], |text| scorer(text));
+            keystream.push(key_byte);
+        }
+
+        let plaintexts = ciphertexts
+            .iter()
+            .map(|c| super::xor::repeat_key_xor(keystream.slice_to(c.len()), c[]))
+            .collect::<Vec<Vec<u8>>>();
+
+        let samples = range(0,max_len)
+            .map(|pos| ciphertexts.iter().filter(|c| c.len() > pos).count())
+            .collect::<Vec<uint>>();
+
+        FixedNonceBreak { keystream: keystream, plaintexts: plaintexts, samples: samples }
+    }
+
+    #[test]
+    fn test_break_fixed_nonce_ctr()
+    {
+        use std::io::File;
+        use super::lang::CharUnigrams;
+
+        let model = CharUnigrams::new("data/english_corpus.txt");
+        let corpus = File::open(&Path::new("data/english_corpus.txt"))
+            .read_to_string()
+            .unwrap();
+        let sentences = corpus[]
+            .split('.')
+            .map(|s| s.trim())
+            .filter(|s| s.len() >= 20)
+            .collect::<Vec<&str>>();
+
+        let key = "YELLOW SUBMARINE".as_bytes();
+        let nonce = [0u8, ..8];
+        let ciphertexts = sentences
+            .iter()
+            .map(|s| ctr_encrypt(key, nonce, s.as_bytes()))
+            .collect::<Vec<Vec<u8>>>();
+
+        let initials = CharUnigrams::new_initials("data/english_corpus.txt");
+        let broken = break_fixed_nonce_ctr(ciphertexts[],
+                                           |text| initials.score_utf8_bytes(text),
+                                           |text| model.score_utf8_bytes(text));
+
+        let min_len = ciphertexts.iter().map(|c| c.len()).min().unwrap();
+        let true_keystream = ctr_encrypt(key, nonce, Vec::from_elem(broken.keystream.len(), 0u8)[]);
+        assert_eq!(true_keystream.slice_to(min_len), broken.keystream.slice_to(min_len));
+
+        // Past the shortest sentence, every byte reached by at least 10
+        // sentences is right. Bytes with only one or two samples can't be
+        // told apart by letter frequencies, so nothing is asserted there.
+        let trusted = range(min_len,broken.keystream.len())
+            .filter(|&pos| broken.samples[pos] >= 10)
+            .collect::<Vec<uint>>();
+        assert!(trusted.len() > 50);
+        for &pos in trusted.iter() {
+            assert_eq!(true_keystream[pos], broken.keystream[pos]);
+        }
+        assert_eq!(broken.samples.len(), broken.keystream.len());
+        assert_eq!(1, broken.samples[broken.samples.len()-1]);
+        assert_eq!(sentences.len(), broken.plaintexts.len());
+    }
+
+    #[test]
+    fn test_ctr_edit()
+    {
+        let key = "YELLOW SUBMARINE".as_bytes();
+        let nonce = [3u8, ..8];
+        let ciphertext = ctr_encrypt(key, nonce, "The quick brown fox jumps over the lazy dog".as_bytes());
+
+        let edited = ctr_edit(ciphertext[], key, nonce, 16, "cat".as_bytes());
+        assert_eq!("The quick brown cat jumps over the lazy dog".as_bytes(),
+                   ctr_decrypt(key, nonce, edited[])[]);
+
+        let edited = ctr_edit(ciphertext[], key, nonce, 35, "sleepy cat".as_bytes());
+        assert_eq!("The quick brown fox jumps over the sleepy cat".as_bytes(),
+                   ctr_decrypt(key, nonce, edited[])[]);
+
+        // Recording the edits and replaying them later works just as well
+        let mut recording: Vec<(uint, Vec<u8>, Vec<u8>)> = Vec::new();
+        let live = recover_ctr_plaintext_via_edit(ciphertext[], 5, |offset, newtext| {
+            let edited = ctr_edit(ciphertext[], key, nonce, offset, newtext);
+            recording.push((offset, newtext.to_vec(), edited.clone()));
+            edited
+        });
+        let replayed = recover_ctr_plaintext_via_edit(ciphertext[], 5, |offset, newtext| {
+            let position = recording
+                .iter()
+                .position(|&(ref o, ref n, _)| *o == offset && n[] == newtext)
+                .unwrap();
+            recording[position].2.clone()
+        });
+        assert_eq!("The quick brown fox jumps over the lazy dog".as_bytes(), live[]);
+        assert_eq!(live, replayed);
+    }
+
+    pub fn uses_ecb_mode(block_size: uint, crypter: |msg:&[u8]| -> Vec<u8>) -> bool
+    {
+        // Construct a message that spans three complete blocks.
+        // This means that at least two complete blocks will be
+        // occupied by the message no matter how it is padded.
+        let msg = Vec::from_elem(block_size*3, 'A' as u8);
+        let ciphertext = crypter(msg[]);
+        super::blocks::has_duplicate_blocks(block_size,ciphertext[])
+    }
+
+    // A toy 8 byte block cipher, so that the modes can be exercised
+    // with something other than AES. It offers no security at all.
+    #[cfg(test)]
+    pub struct ToyCipher {
+        pub key: [u8, ..8]
+    }
+
+    #[cfg(test)]
+    impl BlockCipher for ToyCipher {
+        fn block_size(&self) -> uint { 8 }
+
+        // XOR with the key, then rotate the bytes left by one
+        fn encrypt_block(&self, block: &mut [u8]) {
+            let first = block[0] ^ self.key[0];
+            for i in range(0u,7) {
+                block[i] = block[i+1] ^ self.key[i+1];
+            }
+            block[7] = first;
+        }
+
+        fn decrypt_block(&self, block: &mut [u8]) {
+            let last = block[7];
+            for i in range(1u,8).rev() {
+                block[i] = block[i-1] ^ self.key[i];
+            }
+            block[0] = last ^ self.key[0];
+        }
+    }
+
+    #[test]
+    fn test_modes_with_8_byte_blocks()
+    {
+        let cipher = ToyCipher { key: [3, 1, 4, 1, 5, 9, 2, 6] };
+        let msg = "Sixteen bytes...and then some more".as_bytes();
+        let iv = [7u8, ..8];
+
+        let ecb_ciphertext = ecb_encrypt_with(&cipher, msg);
+        assert_eq!(40, ecb_ciphertext.len());
+        assert_eq!(msg, ecb_decrypt_with(&cipher, ecb_ciphertext[]).unwrap()[]);
+
+        let cbc_ciphertext = cbc_encrypt_with(&cipher, msg, iv);
+        assert_eq!(40, cbc_ciphertext.len());
+        assert_eq!(msg, cbc_decrypt_with(&cipher, cbc_ciphertext[], iv).unwrap()[]);
+
+        let ofb_ciphertext = ofb_apply_with(&cipher, msg, iv);
+        assert_eq!(msg.len(), ofb_ciphertext.len());
+        assert_eq!(msg, ofb_apply_with(&cipher, ofb_ciphertext[], iv)[]);
+
+        for segment_len in [1u, 3, 8].iter() {
+            let cfb_ciphertext = cfb_encrypt_with(&cipher, *segment_len, msg, iv);
+            assert_eq!(msg.len(), cfb_ciphertext.len());
+            assert_eq!(msg, cfb_decrypt_with(&cipher, *segment_len, cfb_ciphertext[], iv)[]);
+        }
+
+        assert_eq!(true, uses_ecb_mode(8, |msg| ecb_encrypt_with(&cipher, msg)));
+        assert_eq!(false, uses_ecb_mode(8, |msg| cbc_encrypt_with(&cipher, msg, iv)));
+
+        let (block_size,suffix_len) = super::blocks::analyze_oracle(|msg: &[u8]| {
+            let mut plaintext = msg.to_vec();
+            plaintext.push_all("secret".as_bytes());
+            ecb_encrypt_with(&cipher, plaintext[])
+        });
+        assert_eq!(8, block_size);
+        assert_eq!(6, suffix_len);
+    }
+
+
+    #[test]
+    fn test_cbc_mode()
+    {
+        let msg = "The cake is a lie, the cake is a lie, THE CAKE IS A LIE!".as_bytes();
+        let key = "yellow submarine".as_bytes();
+
+        let iv1 = [0u8, ..16];
+        let ciphertext1 = cbc_encrypt(key,msg,iv1);
+        let plaintext1  = cbc_decrypt(key,ciphertext1[],iv1);
+
+        let iv2 = [8u8, ..16];
+        let ciphertext2 = cbc_encrypt(key,msg,iv2);
+        let plaintext2  = cbc_decrypt(key,ciphertext2[],iv2);
+
+        assert!(ciphertext1 != ciphertext2);
+        assert_eq!(msg,plaintext1[]);
+        assert_eq!(msg,plaintext2[]);
+
+        let key256 = "yellow submarine yellow submarin".as_bytes();
+        let ciphertext3 = cbc_encrypt(key256,msg,iv1);
+        let plaintext3  = cbc_decrypt(key256,ciphertext3[],iv1);
+        assert!(ciphertext1 != ciphertext3);
+        assert_eq!(msg,plaintext3[]);
+
+        // Flipping the top bit of the last byte of the second to last block
+        // flips it in the final padding byte too, so 0x08 becomes 0x88
+        let mut tampered = ciphertext1.clone();
+        tampered[47] ^= 0x80;
+        assert_eq!(Err(CryptoError::Padding(PaddingError::BadPaddingLength(0x88))),
+                   cbc_decrypt_checked(key,tampered[],iv1));
+        assert_eq!(Err(CryptoError::Padding(PaddingError::BadLength(47))),
+                   cbc_decrypt_checked(key,ciphertext1.slice_to(47),iv1));
+
+        // A bad key is an error rather than a panic
+        assert_eq!(Err(CryptoError::InvalidKeyLength(20)),
+                   cbc_decrypt_checked([0u8, ..20],ciphertext1[],iv1));
+        assert_eq!(Err(CryptoError::InvalidKeyLength(20)),
+                   ecb_decrypt_checked([0u8, ..20],ciphertext1[],iv1));
+        assert_eq!(Err(CryptoError::InvalidKeyLength(20)),
+                   pcbc_decrypt_checked([0u8, ..20],ciphertext1[],iv1));
+        assert_eq!(Err(CryptoError::InvalidKeyLength(20)), cbc_encrypt_checked([0u8, ..20],msg,iv1));
+        assert_eq!(Err(CryptoError::InvalidKeyLength(20)), ecb_encrypt_checked([0u8, ..20],msg,iv1));
+        assert_eq!(Err(CryptoError::InvalidKeyLength(20)), pcbc_encrypt_checked([0u8, ..20],msg,iv1));
+        assert_eq!(Err(CryptoError::InvalidKeyLength(20)), ctr_encrypt_checked([0u8, ..20],[0u8, ..8],msg));
+        assert_eq!(Err(CryptoError::InvalidKeyLength(20)), ctr_decrypt_checked([0u8, ..20],[0u8, ..8],msg));
+        assert_eq!(Ok(ciphertext1.clone()), cbc_encrypt_checked(key,msg,iv1));
+    }
+
+    #[test]
+    fn test_cbc_cts_mode()
+    {
+        use serialize::hex::FromHex;
+
+        // RFC 3962 uses CS3 with a zero iv. CS1 and CS2 only move the
+        // stolen bytes around.
+        let cipher = aes_for_key("chicken teriyaki".as_bytes()).unwrap();
+        let iv = [0u8, ..16];
+        let msg = "I would like the General Gau's Chicken, please,".as_bytes();
+        let cases = [
+            (17u, "97c6353568f2bf8cb4d8a580362da7ff7f",
+                  "c6353568f2bf8cb4d8a580362da7ff7f97"),
+            (31u, "97687268d6ecccc0c07b25e25ecfe5fc00783e0efdb2c1d445d4c8eff7ed22",
+                  "fc00783e0efdb2c1d445d4c8eff7ed2297687268d6ecccc0c07b25e25ecfe5"),
+            (32u, "97687268d6ecccc0c07b25e25ecfe58439312523a78662d5be7fcbcc98ebf5a8",
+                  "39312523a78662d5be7fcbcc98ebf5a897687268d6ecccc0c07b25e25ecfe584"),
+            (47u, "97687268d6ecccc0c07b25e25ecfe58439312523a78662d5be7fcbcc98ebf5b3fffd940c16a18c1b5549d2f838029e",
+                  "97687268d6ecccc0c07b25e25ecfe584b3fffd940c16a18c1b5549d2f838029e39312523a78662d5be7fcbcc98ebf5")];
+        for &(len, cs1, cs3) in cases.iter()
+        {
+            let plaintext = msg.slice_to(len);
+            let cs1 = cs1.from_hex().unwrap();
+            let cs3 = cs3.from_hex().unwrap();
+            let cs2 = if len % 16 == 0 { cs1.clone() } else { cs3.clone() };
+
+            assert_eq!(cs1, cbc_cts_encrypt_with(&cipher, CtsVariant::Cs1, plaintext, iv));
+            assert_eq!(cs2, cbc_cts_encrypt_with(&cipher, CtsVariant::Cs2, plaintext, iv));
+            assert_eq!(cs3, cbc_cts_encrypt_with(&cipher, CtsVariant::Cs3, plaintext, iv));
+            assert_eq!(plaintext, cbc_cts_decrypt_with(&cipher, CtsVariant::Cs1, cs1[], iv)[]);
+            assert_eq!(plaintext, cbc_cts_decrypt_with(&cipher, CtsVariant::Cs2, cs2[], iv)[]);
+            assert_eq!(plaintext, cbc_cts_decrypt_with(&cipher, CtsVariant::Cs3, cs3[], iv)[]);
+        }
+
+        // A block aligned message is plain CBC without the padding block
+        assert_eq!(cbc_encrypt_blocks(&cipher, msg.slice_to(32), iv),
+                   cbc_cts_encrypt_with(&cipher, CtsVariant::Cs1, msg.slice_to(32), iv));
+
+        // Every length from one block up round trips, with 8 byte blocks too
+        let toy = ToyCipher { key: [3, 1, 4, 1, 5, 9, 2, 6] };
+        for len in range(8u,msg.len()+1)
+        {
+            for variant in [CtsVariant::Cs1, CtsVariant::Cs2, CtsVariant::Cs3].iter()
+            {
+                let ciphertext = cbc_cts_encrypt_with(&toy, variant.clone(), msg.slice_to(len), iv.slice_to(8));
+                assert_eq!(len, ciphertext.len());
+                assert_eq!(msg.slice_to(len),
+                           cbc_cts_decrypt_with(&toy, variant.clone(), ciphertext[], iv.slice_to(8))[]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_xts_mode()
+    {
+        use serialize::hex::FromHex;
+
+        // IEEE 1619 vectors 1, 4 and 15
+        let ciphertext = xts_encrypt([0u8, ..32], 0, [0u8, ..32]);
+        assert_eq!("917cf69ebd68b2ec9b9fe9a3eadda692cd43d2f59598ed858c02c2652fbf922e".from_hex().unwrap(),
+                   ciphertext);
+        assert_eq!(vec![0u8, ..32], xts_decrypt([0u8, ..32], 0, ciphertext[]));
+
+        let key = "2718281828459045235360287471352631415926535897932384626433832795".from_hex().unwrap();
+        let sector = Vec::from_fn(512, |i| i as u8);
+        let ciphertext = xts_encrypt(key[], 0, sector[]);
+        assert_eq!("27a7479befa1d476489f308cd4cfa6e2a96e4bbe3208ff25287dd3819616e89c".from_hex().unwrap()[],
+                   ciphertext.slice_to(32));
+        assert_eq!("0a282df920147beabe421ee5319d0568".from_hex().unwrap()[],
+                   ciphertext.slice_from(496));
+        assert_eq!(sector, xts_decrypt(key[], 0, ciphertext[]));
+
+        let key = "fffefdfcfbfaf9f8f7f6f5f4f3f2f1f0bfbebdbcbbbab9b8b7b6b5b4b3b2b1b0".from_hex().unwrap();
+        let plaintext = Vec::from_fn(17, |i| i as u8);
+        let ciphertext = xts_encrypt(key[], 0x123456789a, plaintext[]);
+        assert_eq!("6c1625db4671522d3d7599601de7ca09ed".from_hex().unwrap(), ciphertext);
+        assert_eq!(plaintext, xts_decrypt(key[], 0x123456789a, ciphertext[]));
+
+        for len in range(16u,50)
+        {
+            let ciphertext = xts_encrypt(key[], 7, sector.slice_to(len));
+            assert_eq!(len, ciphertext.len());
+            assert_eq!(sector.slice_to(len), xts_decrypt(key[], 7, ciphertext[])[]);
+        }
+    }
+
+    #[test]
+    fn test_xts_malleability()
+    {
+        use serialize::hex::FromHex;
+
+        let key = "2718281828459045235360287471352631415926535897932384626433832795".from_hex().unwrap();
+        let old_sector = Vec::from_fn(512, |i| i as u8);
+        let mut new_sector = old_sector.clone();
+        for i in range(64u,80) {
+            new_sector[i] = 0xff;
+        }
+        let old_ciphertext = xts_encrypt(key[], 3, old_sector[]);
+        let new_ciphertext = xts_encrypt(key[], 3, new_sector[]);
+
+        // Each block is encrypted on its own, so a changed block shows
+        // exactly where a sector was written
+        let changed_blocks = range(0u,32)
+            .filter(|&n| super::blocks::nth_block(16, old_ciphertext[], n) != super::blocks::nth_block(16, new_ciphertext[], n))
+            .collect::<Vec<uint>>();
+        assert_eq!(vec![4u], changed_blocks);
+
+        // Flipping a bit garbles only the block it lands in
+        let mut tampered = new_ciphertext.clone();
+        tampered[100] ^= 1;
+        let decrypted = xts_decrypt(key[], 3, tampered[]);
+        assert_eq!(new_sector.slice_to(96), decrypted.slice_to(96));
+        assert!(new_sector.slice(96, 112) != decrypted.slice(96, 112));
+        assert_eq!(new_sector.slice_from(112), decrypted.slice_from(112));
+
+        // An old block can be put back in the same place to roll it back,
+        // though moving it to another block or sector only gives garbage
+        let mut rolled_back = new_ciphertext.clone();
+        super::blocks::replace_block(16, rolled_back.as_mut_slice(), 4,
+                                     super::blocks::nth_block(16, old_ciphertext[], 4));
+        assert_eq!(old_sector, xts_decrypt(key[], 3, rolled_back[]));
+
+        let mut moved = new_ciphertext.clone();
+        super::blocks::replace_block(16, moved.as_mut_slice(), 5,
+                                     super::blocks::nth_block(16, new_ciphertext[], 0));
+        assert!(new_sector.slice_to(16) != xts_decrypt(key[], 3, moved[]).slice(80, 96));
+        assert!(old_sector != xts_decrypt(key[], 4, old_ciphertext[]));
+    }
+
+    #[test]
+    fn test_pcbc_mode()
+    {
+        let msg = "The cake is a lie, the cake is a lie, THE CAKE IS A LIE!".as_bytes();
+        let key = "yellow submarine".as_bytes();
+        let iv = [5u8, ..16];
+
+        let ciphertext = pcbc_encrypt(key,msg,iv);
+        assert_eq!(64, ciphertext.len());
+        assert!(ciphertext != cbc_encrypt(key,msg,iv));
+        assert_eq!(msg, pcbc_decrypt(key,ciphertext[],iv)[]);
+
+        let cipher = ToyCipher { key: [3, 1, 4, 1, 5, 9, 2, 6] };
+        let toy_ciphertext = pcbc_encrypt_with(&cipher, msg, iv.slice_to(8));
+        assert_eq!(msg, pcbc_decrypt_with(&cipher, toy_ciphertext[], iv.slice_to(8)).unwrap()[]);
+        assert_eq!(Err(PaddingError::BadLength(63)),
+                   pcbc_decrypt_with(&aes_for_key(key).unwrap(), ciphertext.slice_to(63), iv));
+    }
+
+    #[test]
+    fn test_pcbc_error_propagation()
+    {
+        let bs = 16u;
+        let key = "yellow submarine".as_bytes();
+        let cipher = aes_for_key(key).unwrap();
+        let iv = [5u8, ..16];
+        let msg = Vec::from_fn(bs*5, |i| 'a' as u8 + (i % 26) as u8);
+        let pcbc = pcbc_encrypt_blocks(&cipher, msg[], iv);
+        let cbc = cbc_encrypt_blocks(&cipher, msg[], iv);
+
+        let blocks_intact = |decrypted: &[u8]| -> Vec<bool> {
+            range(0u,5)
+                .map(|n| super::blocks::nth_block(bs, decrypted, n) == super::blocks::nth_block(bs, msg[], n))
+                .collect()
+        };
+
+        // A flipped byte in CBC scrambles its own block and flips the same
+        // byte in the next, but PCBC carries the damage to the very end
+        let mut tampered = cbc.clone();
+        tampered[bs+3] ^= 1;
+        let decrypted = cbc_decrypt_blocks(&cipher, tampered[], iv);
+        assert_eq!(vec![true, false, false, true, true], blocks_intact(decrypted[]));
+        assert_eq!(msg[bs*2+3] ^ 1, decrypted[bs*2+3]);
+
+        let mut tampered = pcbc.clone();
+        tampered[bs+3] ^= 1;
+        let decrypted = pcbc_decrypt_blocks(&cipher, tampered[], iv);
+        assert_eq!(vec![true, false, false, false, false], blocks_intact(decrypted[]));
+
+        // Swapping two adjacent PCBC blocks garbles just those two, since
+        // plaintext xor ciphertext over the pair comes out the same
+        let mut swapped = pcbc.clone();
+        let block1 = super::blocks::nth_block(bs, pcbc[], 1).to_vec();
+        let block2 = super::blocks::nth_block(bs, pcbc[], 2).to_vec();
+        super::blocks::replace_block(bs, swapped.as_mut_slice(), 1, block2[]);
+        super::blocks::replace_block(bs, swapped.as_mut_slice(), 2, block1[]);
+        let decrypted = pcbc_decrypt_blocks(&cipher, swapped[], iv);
+        assert_eq!(vec![true, false, false, true, true], blocks_intact(decrypted[]));
+
+        // The same swap in CBC also leaves the block after the pair garbled
+        let mut swapped = cbc.clone();
+        let block1 = super::blocks::nth_block(bs, cbc[], 1).to_vec();
+        let block2 = super::blocks::nth_block(bs, cbc[], 2).to_vec();
+        super::blocks::replace_block(bs, swapped.as_mut_slice(), 1, block2[]);
+        super::blocks::replace_block(bs, swapped.as_mut_slice(), 2, block1[]);
+        let decrypted = cbc_decrypt_blocks(&cipher, swapped[], iv);
+        assert_eq!(vec![true, false, false, false, true], blocks_intact(decrypted[]));
+    }
+
+    #[test]
+    fn test_ofb_cfb_modes()
+    {
+        use serialize::hex::FromHex;
+
+        // NIST SP 800-38A F.3.7, F.3.13 and F.4.1
+        let key = "2b7e151628aed2a6abf7158809cf4f3c".from_hex().unwrap();
+        let iv = "000102030405060708090a0b0c0d0e0f".from_hex().unwrap();
+        let plaintext = "6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e5130c81c46a35ce411e5fbc1191a0a52eff69f2445df4f9b17ad2b417be66c3710"
+            .from_hex()
+            .unwrap();
+
+        let cfb8 = "3b79424c9c0dd436bace9e0ed4586a4f32b9".from_hex().unwrap();
+        assert_eq!(cfb8, cfb8_encrypt(key[], plaintext.slice_to(18), iv[]));
+        assert_eq!(plaintext.slice_to(18), cfb8_decrypt(key[], cfb8[], iv[])[]);
+
+        let cfb128 = "3b3fd92eb72dad20333449f8e83cfb4ac8a64537a0b3a93fcde3cdad9f1ce58b26751f67a3cbb140b1808cf187a4f4dfc04b05357c5d1c0eeac4c66f9ff7f2e6"
+            .from_hex()
+            .unwrap();
+        assert_eq!(cfb128, cfb128_encrypt(key[], plaintext[], iv[]));
+        assert_eq!(plaintext, cfb128_decrypt(key[], cfb128[], iv[]));
+
+        let ofb = "3b3fd92eb72dad20333449f8e83cfb4a7789508d16918f03f53c52dac54ed8259740051e9c5fecf64344f7a82260edcc304c6528f659c77866a510d9c1d6ae5e"
+            .from_hex()
+            .unwrap();
+        assert_eq!(ofb, ofb_encrypt(key[], plaintext[], iv[]));
+        assert_eq!(plaintext, ofb_decrypt(key[], ofb[], iv[]));
+
+        // No padding, so a partial last block just gets cut short
+        assert_eq!(cfb128.slice_to(21), cfb128_encrypt(key[], plaintext.slice_to(21), iv[])[]);
+        assert_eq!(ofb.slice_to(21), ofb_encrypt(key[], plaintext.slice_to(21), iv[])[]);
+    }
+
+    #[test]
+    fn test_cfb8_zero_iv()
+    {
+        // Zerologon: with an all zero iv, an all zero plaintext encrypts to
+        // all zeros under CFB-8 whenever the first byte of E(k, 0) is zero,
+        // which happens for about one key in 256
+        let plaintext = [0u8, ..8];
+        let iv = [0u8, ..16];
+        let mut tries = 0u;
+        loop {
+            tries += 1;
+            let key = Vec::from_fn(16, |_| rand::random::<u8>());
+            let mut first_block = [0u8, ..16];
+            aes_for_key(key[]).unwrap().encrypt_block(first_block.as_mut_slice());
+
+            let ciphertext = cfb8_encrypt(key[], plaintext, iv);
+            if first_block[0] == 0 {
+                assert_eq!(plaintext[], ciphertext[]);
+                break;
+            }
+            assert!(plaintext[] != ciphertext[]);
+            assert!(tries < 256*20);
+        }
+    }
+
+    #[test]
+    fn test_tags_match()
+    {
+        let tag = [1u8, 2, 3, 4];
+        assert!(tags_match(tag, [1, 2, 3, 4]));
+        assert!(!tags_match(tag, [1, 2, 3, 5]));
+        assert!(!tags_match(tag, [0, 2, 3, 4]));
+        assert!(!tags_match(tag, [1, 2, 3]));
+        assert!(!tags_match(tag, [1, 2, 3, 4, 5]));
+        assert!(!tags_match(tag, []));
+    }
+
+    pub struct SuffixDecryption {
+        pub plaintext: Vec<u8>,
+        pub oracle_queries: uint
+    }
+
+    // Byte-at-a-time decryption of whatever an ECB oracle appends to our
+    // data. To learn byte i of the suffix we push it to the end of a block
+    // with filler bytes, and remember that ciphertext block. Then we try
+    // each candidate byte behind the previous block_size-1 bytes of the
+    // stream until one encrypts to the same block.
+    pub fn ecb_suffix_decrypter(filler: u8, oracle: |msg: &[u8]| -> Vec<u8>) -> SuffixDecryption {
+        let mut oracle_queries = 0u;
+        let plaintext = {
+            let mut counted_oracle = |msg: &[u8]| -> Vec<u8> {
+                oracle_queries += 1;
+                oracle(msg)
+            };
+
+            let (block_size,suffix_len) = super::blocks::analyze_oracle(|msg| counted_oracle(msg));
+
+            // Ciphertexts with 0 to block_size-1 filler bytes in front of the
+            // suffix, which between them put every suffix byte at the end of
+            // some block
+            let shifted_ciphertexts = Vec::from_fn(block_size, |pad_len| {
+                counted_oracle(Vec::from_elem(pad_len, filler)[])
+            });
+
+            // The stream as the oracle sees it with block_size-1 filler bytes
+            // in front, so there are always enough bytes before the one we want
+            let mut stream = Vec::from_elem(block_size - 1, filler);
+
+            for byte_no in range(0u,suffix_len)
+            {
+                let pad_len = block_size - 1 - (byte_no%block_size);
+                let block_no = byte_no / block_size;
+                let target = super::blocks::nth_block(
+                    block_size,
+                    shifted_ciphertexts[pad_len][],
+                    block_no);
+
+                let mut candidate_block = stream.slice_from(stream.len() - (block_size - 1)).to_vec();
+                candidate_block.push(0);
+
+                let mut found = None;
+                for candidate in range(0u,256)
+                {
+                    candidate_block[block_size - 1] = candidate as u8;
+                    let ciphertext = counted_oracle(candidate_block[]);
+                    if ciphertext.slice_to(block_size) == target {
+                        found = Some(candidate as u8);
+                        break;
+                    }
+                }
+
+                match found {
+                    Some(byte) => stream.push(byte),
+                    None => break // the suffix turned out shorter than we thought
+                }
+            }
+
+            stream.slice_from(block_size - 1).to_vec()
+        };
+
+        SuffixDecryption {
+            plaintext: plaintext,
+            oracle_queries: oracle_queries
+        }
+    }
+
+    // As ecb_suffix_decrypter, for an oracle which puts a fixed prefix of
+    // unknown length in front of our data. Once we know how long the
+    // prefix is, we pad it out to a whole number of blocks and hide those
+    // blocks from ecb_suffix_decrypter.
+    pub fn ecb_prefixed_suffix_decrypter(filler: u8, oracle: |msg: &[u8]| -> Vec<u8>) -> SuffixDecryption {
+        let (block_size,_) = super::blocks::analyze_oracle(|msg|oracle(msg));
+        let prefix_len = super::blocks::find_prefix_len(block_size, |msg|oracle(msg));
+
+        let pad_len = (block_size - prefix_len % block_size) % block_size;
+        let skip_len = prefix_len + pad_len;
+
+        ecb_suffix_decrypter(filler, |msg| {
+            let mut padded_msg = Vec::from_elem(pad_len, filler);
+            padded_msg.push_all(msg);
+            oracle(padded_msg[]).slice_from(skip_len).to_vec()
+        })
+    }
+
+    pub struct BitflipPlan {
+        pub iv: Vec<u8>,
+        pub ciphertext: Vec<u8>,
+        // The block whose plaintext is garbage after the edit. Editing
+        // block 0 only changes the iv, so nothing is scrambled.
+        pub scrambled_block: Option<uint>
+    }
+
+    // Edit a CBC message so that block target_block decrypts to wanted
+    // instead of known. Flipping bits in the previous ciphertext block (or
+    // the iv) flips the same bits of the target block's plaintext, but
+    // scrambles the plaintext of the block we changed.
+    pub fn plan_cbc_bitflip(block_size: uint,
+                            iv: &[u8],
+                            ciphertext: &[u8],
+                            target_block: uint,
+                            known: &[u8],
+                            wanted: &[u8]) -> BitflipPlan {
+        assert_eq!(iv.len(), block_size);
+        assert_eq!(known.len(), block_size);
+        assert_eq!(wanted.len(), block_size);
+        assert!((target_block + 1)*block_size <= ciphertext.len());
+
+        let flips = super::xor::repeat_key_xor(known, wanted);
+        let mut edited_iv = iv.to_vec();
+        let mut edited_ciphertext = ciphertext.to_vec();
+
+        if target_block == 0 {
+            edited_iv = super::xor::repeat_key_xor(flips[], iv);
+            return BitflipPlan {
+                iv: edited_iv,
+                ciphertext: edited_ciphertext,
+                scrambled_block: None
+            };
+        }
+
+        let prev_block = super::blocks::nth_block(block_size, ciphertext, target_block - 1);
+        super::blocks::replace_block(
+            block_size,
+            edited_ciphertext.as_mut_slice(),
+            target_block - 1,
+            super::xor::repeat_key_xor(flips[], prev_block)[]);
+        BitflipPlan {
+            iv: edited_iv,
+            ciphertext: edited_ciphertext,
+            scrambled_block: Some(target_block - 1)
+        }
+    }
+
+    #[test]
+    fn test_plan_cbc_bitflip()
+    {
+        let key = "YELLOW SUBMARINE".as_bytes();
+        let iv = [1u8, ..16];
+        let msg = "user=alice......role=guest......trailing block".as_bytes();
+        let ciphertext = cbc_encrypt(key, msg, iv);
+
+        let plan = plan_cbc_bitflip(16, iv, ciphertext[], 1,
+                                    "role=guest......".as_bytes(),
+                                    "role=admin......".as_bytes());
+        assert_eq!(Some(0), plan.scrambled_block);
+        assert_eq!(iv[], plan.iv[]);
+        let edited = cbc_decrypt(key, plan.ciphertext[], plan.iv[]);
+        assert!(edited.slice(0,16) != msg.slice(0,16));
+        assert_eq!("role=admin......trailing block".as_bytes(), edited.slice_from(16));
+
+        // The first block is edited through the iv, which scrambles nothing
+        let plan = plan_cbc_bitflip(16, iv, ciphertext[], 0,
+                                    "user=alice......".as_bytes(),
+                                    "user=root.......".as_bytes());
+        assert_eq!(None, plan.scrambled_block);
+        assert_eq!(ciphertext, plan.ciphertext);
+        assert_eq!("user=root.......role=guest......trailing block".as_bytes(),
+                   cbc_decrypt(key, plan.ciphertext[], plan.iv[])[]);
+    }
+
+    // Recover the key from a CBC system which uses the key as its iv. The
+    // oracle decrypts a ciphertext and hands the plaintext back in an
+    // error when it objects to it (for instance for not being ASCII). We
+    // send C1 || 0 || C1, followed by the last two real blocks so that the
+    // padding stays valid. That decrypts to D(C1)^key, junk, D(C1)^0, so
+    // the first and third blocks xor to the key. The key is then checked
+    // by decrypting the original ciphertext with it.
+    pub fn recover_key_used_as_iv(block_size: uint,
+                                  ciphertext: &[u8],
+                                  oracle: |ciphertext: &[u8]| -> Result<(), Vec<u8>>) -> Option<Vec<u8>> {
+        assert_eq!(ciphertext.len() % block_size, 0);
+        let block_count = ciphertext.len() / block_size;
+        assert!(block_count >= 2);
+
+        let first_block = super::blocks::nth_block(block_size, ciphertext, 0);
+        let mut attack: Vec<u8> = Vec::new();
+        attack.push_all(first_block);
+        attack.push_all(Vec::from_elem(block_size, 0u8)[]);
+        attack.push_all(first_block);
+        attack.push_all(ciphertext.slice_from((block_count - 2)*block_size));
+
+        let plaintext = match oracle(attack[]) {
+            Ok(()) => return None,
+            Err(plaintext) => plaintext
+        };
+        if plaintext.len() < 3*block_size {
+            return None;
+        }
+
+        let key = super::xor::repeat_key_xor(
+            super::blocks::nth_block(block_size, plaintext[], 0),
+            super::blocks::nth_block(block_size, plaintext[], 2));
+
+        match cbc_decrypt_checked(key[], ciphertext, key[]) {
+            Ok(_) => Some(key),
+            Err(_) => None
+        }
+    }
+
+    // Recover the plaintext of one CBC block using an oracle which is
+    // given an iv and ciphertext, and only reports whether they decrypt to
+    // validly padded plaintext. prev_block is the ciphertext block (or iv)
+    // which came before block in the real message. We forge a one block
+    // message with our own iv in front of block, so each block is
+    // attacked independently of the rest of the message.
+    pub fn padding_oracle_decrypt_block(prev_block: &[u8],
+                                        block: &[u8],
+                                        oracle: |iv: &[u8], ciphertext: &[u8]| -> bool) -> Result<Vec<u8>, PaddingError> {
+        let block_size = block.len();
+        assert_eq!(prev_block.len(), block_size);
+
+        // The raw block decryption, before the xor with prev_block
+        let mut intermediate = Vec::from_elem(block_size, 0u8);
+
+        for pad_len in range(1u,block_size + 1)
+        {
+            let pos = block_size - pad_len;
+
+            // Make the bytes we already know decrypt to pad_len
+            let mut forged_iv = Vec::from_elem(block_size, 0u8);
+            for i in range(pos + 1,block_size) {
+                forged_iv[i] = intermediate[i] ^ pad_len as u8;
+            }
+
+            let mut found = None;
+            for guess in range(0u,256)
+            {
+                forged_iv[pos] = guess as u8;
+                if !oracle(forged_iv[], block) {
+                    continue;
+                }
+
+                // When hunting for the last byte, valid padding might
+                // really be \x02\x02 or longer rather than \x01. Changing
+                // the byte before it only keeps the padding valid for \x01.
+                if pad_len == 1 && pos > 0 {
+                    forged_iv[pos - 1] ^= 0xff;
+                    let still_valid = oracle(forged_iv[], block);
+                    forged_iv[pos - 1] ^= 0xff;
+                    if !still_valid {
+                        continue;
+                    }
+                }
+
+                found = Some(guess as u8);
+                break;
+            }
+
+            match found {
+                Some(guess) => intermediate[pos] = guess ^ pad_len as u8,
+                None => return Err(PaddingError::OracleRejectedAll(pos))
+            }
+        }
+
+        Ok(super::xor::repeat_key_xor(prev_block, intermediate[]))
+    }
+
+    // Recover the whole plaintext of a CBC message, one block at a time,
+    // using only a padding oracle (see padding_oracle_decrypt_block)
+    pub fn padding_oracle_decrypt(block_size: uint,
+                                  iv: &[u8],
+                                  ciphertext: &[u8],
+                                  oracle: |iv: &[u8], ciphertext: &[u8]| -> bool) -> Result<Vec<u8>, PaddingError> {
+        assert_eq!(iv.len(), block_size);
+        if ciphertext.len() % block_size != 0 {
+            return Err(PaddingError::BadLength(ciphertext.len()));
+        }
+
+        let mut plaintext: Vec<u8> = Vec::new();
+        let mut prev_block = iv;
+        for block in ciphertext.chunks(block_size)
+        {
+            let decrypted = try!(padding_oracle_decrypt_block(
+                prev_block,
+                block,
+                |iv, ciphertext| oracle(iv, ciphertext)));
+            plaintext.push_all(decrypted[]);
+            prev_block = block;
+        }
+
+        try!(plaintext.pkcs7_trim(block_size));
+        Ok(plaintext)
+    }
+
+    pub struct ForgedCiphertext {
+        pub iv: Vec<u8>,
+        pub ciphertext: Vec<u8>,
+        pub oracle_queries: uint
+    }
+
+    // CBC-R: make an iv and ciphertext which decrypt to any plaintext we
+    // like, using only a padding oracle. Starting from a random last block,
+    // we recover its raw decryption with the oracle and choose the block
+    // before it so that the two xor to the plaintext we want. That block
+    // is then attacked in turn, working backwards until we reach the iv.
+    pub fn padding_oracle_encrypt(block_size: uint,
+                                  plaintext: &[u8],
+                                  oracle: |iv: &[u8], ciphertext: &[u8]| -> bool) -> Result<ForgedCiphertext, PaddingError> {
+        let mut padded = plaintext.to_vec();
+        padded.pkcs7_extend(block_size);
+
+        let zero_block = Vec::from_elem(block_size, 0u8);
+        let mut oracle_queries = 0u;
+        let mut next_block = Vec::from_fn(block_size, |_| rand::random::<u8>());
+
+        // built back to front, ending with the iv
+        let mut blocks = vec![next_block.clone()];
+        for plain_block in padded.chunks(block_size).rev()
+        {
+            let intermediate = try!(padding_oracle_decrypt_block(
+                zero_block[],
+                next_block[],
+                |iv, ciphertext| {
+                    oracle_queries += 1;
+                    oracle(iv, ciphertext)
+                }));
+            next_block = super::xor::repeat_key_xor(intermediate[], plain_block);
+            blocks.push(next_block.clone());
+        }
+        blocks.as_mut_slice().reverse();
+
+        let mut ciphertext: Vec<u8> = Vec::new();
+        for block in blocks.slice_from(1).iter() {
+            ciphertext.push_all(block[]);
+        }
+        Ok(ForgedCiphertext {
+            iv: blocks[0].clone(),
+            ciphertext: ciphertext,
+            oracle_queries: oracle_queries
+        })
+    }
+
+    #[test]
+    fn test_ecb_suffix_decrypter()
+    {
+        let suffix = "Rollin' in my 5.0\nWith my rag-top down so my hair can blow".as_bytes();
+        let aes = Aes::new("yELlOW SuBMaRiNe".as_bytes()).unwrap();
+        let toy = ToyCipher { key: [2, 7, 1, 8, 2, 8, 1, 8] };
+
+        let aes_result = ecb_suffix_decrypter('A' as u8, |msg| {
+            let mut plaintext = msg.to_vec();
+            plaintext.push_all(suffix);
+            ecb_encrypt_with(&aes, plaintext[])
+        });
+        assert_eq!(suffix, aes_result.plaintext[]);
+
+        let toy_result = ecb_suffix_decrypter('*' as u8, |msg| {
+            let mut plaintext = msg.to_vec();
+            plaintext.push_all(suffix);
+            ecb_encrypt_with(&toy, plaintext[])
+        });
+        assert_eq!(suffix, toy_result.plaintext[]);
+
+        // The suffix is ASCII, so stopping at the first match takes at most
+        // 128 candidates per byte. On top of that come block_size queries
+        // for the target blocks and at most block_size+1 in analyze_oracle.
+        assert!(aes_result.oracle_queries <= 128*suffix.len() + 16 + (16 + 1));
+        assert!(toy_result.oracle_queries <= 128*suffix.len() + 8 + (8 + 1));
+    }
+
+    #[test]
+    fn test_padding_oracle_decrypt()
+    {
+        let key = Vec::from_fn(16, |_| rand::random::<u8>());
+        let iv = Vec::from_fn(16, |_| rand::random::<u8>());
+        let cipher = Aes::new(key[]).unwrap();
+
+        for msg_len in range(0u,40)
+        {
+            let msg = Vec::from_fn(msg_len, |i| (i*7) as u8);
+            let ciphertext = cbc_encrypt_with(&cipher, msg[], iv[]);
+            let decrypted = padding_oracle_decrypt(
+                16,
+                iv[],
+                ciphertext[],
+                |iv, ciphertext| cbc_decrypt_with(&cipher, ciphertext, iv).is_ok());
+            assert_eq!(Ok(msg), decrypted);
+        }
+
+        // Find a block whose raw decryption ends in \x02 followed by
+        // anything, so that both \x01 and \x02\x02 padding can be forged
+        // when looking for the last byte.
+        let mut block = Vec::from_elem(16, 0u8);
+        loop {
+            for b in block.iter_mut() {
+                *b = rand::random::<u8>();
+            }
+            let mut raw = block.clone();
+            cipher.decrypt_block(raw.as_mut_slice());
+            if raw[14] == 2 {
+                break;
+            }
+        }
+        let zero_iv = [0u8, ..16];
+        let mut expected = block.clone();
+        cipher.decrypt_block(expected.as_mut_slice());
+        assert_eq!(Ok(expected),
+                   padding_oracle_decrypt_block(
+                       zero_iv,
+                       block[],
+                       |iv, ciphertext| cbc_decrypt_with(&cipher, ciphertext, iv).is_ok()));
+
+        // Something that isn't a padding oracle gives an error, not a panic
+        let iv = [0u8, ..16];
+        assert_eq!(Err(PaddingError::OracleRejectedAll(15)),
+                   padding_oracle_decrypt_block(iv, block[], |_, _| false));
+        assert_eq!(Err(PaddingError::OracleRejectedAll(15)),
+                   padding_oracle_decrypt(16, iv, block[], |_, _| false));
+        assert_eq!(Some(PaddingError::OracleRejectedAll(15)),
+                   padding_oracle_encrypt(16, "YELLOW SUBMARINE".as_bytes(), |_, _| false).err());
+    }
+
+    #[test]
+    fn test_padding_oracle_encrypt()
+    {
+        let key = Vec::from_fn(16, |_| rand::random::<u8>());
+        let cipher = Aes::new(key[]).unwrap();
+        let wanted = "comment1=cooking%20MCs;userdata=x;admin=true;comment2=%20like%20a%20pound%20of%20bacon".as_bytes();
+
+        let forged = padding_oracle_encrypt(
+            16,
+            wanted,
+            |iv, ciphertext| cbc_decrypt_with(&cipher, ciphertext, iv).is_ok()).unwrap();
+
+        assert_eq!(96, forged.ciphertext.len());
+        assert_eq!(Ok(wanted.to_vec()),
+                   cbc_decrypt_with(&cipher, forged.ciphertext[], forged.iv[]));
+
+        // At most 256 guesses for each byte of a block. Only the last byte
+        // re-checks accepted guesses for \x02\x02 style false positives,
+        // at most once per guess, which adds up to 256 more per block.
+        assert!(forged.oracle_queries > 0);
+        assert!(forged.oracle_queries <= 6*(16*256 + 256));
+    }
+}
+
+pub mod gcm {
+    use std::rand;
+    use super::crypto::{aes_for_key, tags_match, BadTag, BlockCipher, Ctr, CtrLayout, Endian};
+
+    // An element of GF(2^128) in GCM's bit order, where the first bit of
+    // a block is the coefficient of x^0 and the field polynomial is
+    // x^128 + x^7 + x^2 + x + 1. hi holds the first 8 bytes, big endian.
+    #[deriving(Clone, PartialEq, Show)]
+    pub struct Gf128 {
+        pub hi: u64,
+        pub lo: u64
+    }
+
+    impl Gf128 {
+        pub fn zero() -> Gf128 {
+            Gf128 { hi: 0, lo: 0 }
+        }
+
+        pub fn one() -> Gf128 {
+            Gf128 { hi: 1u64 << 63, lo: 0 }
+        }
+
+        // Short blocks are padded with zeros
+        pub fn from_bytes(bytes: &[u8]) -> Gf128 {
+            assert!(bytes.len() <= 16);
+            let mut hi = 0u64;
+            let mut lo = 0u64;
+            for i in range(0u,16)
+            {
+                let byte = if i < bytes.len() { bytes[i] as u64 } else { 0 };
+                if i < 8 {
+                    hi = (hi << 8) | byte;
+                } else {
+                    lo = (lo << 8) | byte;
+                }
+            }
+            Gf128 { hi: hi, lo: lo }
+        }
+
+        pub fn to_bytes(&self) -> Vec<u8> {
+            Vec::from_fn(16, |i| {
+                if i < 8 {
+                    (self.hi >> (56 - 8*i)) as u8
+                } else {
+                    (self.lo >> (56 - 8*(i-8))) as u8
+                }
+            })
+        }
+
+        pub fn random() -> Gf128 {
+            Gf128 { hi: rand::random::<u64>(), lo: rand::random::<u64>() }
+        }
+
+        pub fn is_zero(&self) -> bool {
+            self.hi == 0 && self.lo == 0
+        }
+
+        pub fn add(&self, other: &Gf128) -> Gf128 {
+            Gf128 { hi: self.hi ^ other.hi, lo: self.lo ^ other.lo }
+        }
+
+        // Shift and add, as in NIST SP 800-38D algorithm 1. Shifting right
+        // multiplies by x in this bit order, and the bit that falls off the
+        // end is reduced back in as 0xe1 at the top.
+        pub fn mul(&self, other: &Gf128) -> Gf128 {
+            let mut product = Gf128::zero();
+            let mut v = *other;
+            for i in range(0u,128)
+            {
+                let bit = if i < 64 { self.hi >> (63 - i) } else { self.lo >> (127 - i) };
+                if bit & 1 == 1 {
+                    product = product.add(&v);
+                }
+                let carry = v.lo & 1;
+                v.lo = (v.lo >> 1) | (v.hi << 63);
+                v.hi >>= 1;
+                if carry == 1 {
+                    v.hi ^= 0xe1u64 << 56;
+                }
+            }
+            product
+        }
+
+        // a^(2^128 - 2), which is the product of a^2, a^4, ... a^(2^127)
+        pub fn inverse(&self) -> Gf128 {
+            assert!(!self.is_zero());
+            let mut result = Gf128::one();
+            let mut power = *self;
+            for _ in range(1u,128)
+            {
+                power = power.mul(&power);
+                result = result.mul(&power);
+            }
+            result
+        }
+    }
+
+    // The GHASH input: the aad and ciphertext, each zero padded to whole
+    // blocks, then a block holding both their lengths in bits
+    fn ghash_blocks(aad: &[u8], ciphertext: &[u8]) -> Vec<Gf128> {
+        let mut blocks: Vec<Gf128> = Vec::new();
+        for block in aad.chunks(16) {
+            blocks.push(Gf128::from_bytes(block));
+        }
+        for block in ciphertext.chunks(16) {
+            blocks.push(Gf128::from_bytes(block));
+        }
+        blocks.push(Gf128 { hi: aad.len() as u64 * 8, lo: ciphertext.len() as u64 * 8 });
+        blocks
+    }
+
+    pub fn ghash(h: &Gf128, aad: &[u8], ciphertext: &[u8]) -> Gf128 {
+        ghash_blocks(aad, ciphertext)
+            .iter()
+            .fold(Gf128::zero(), |y, x| y.add(x).mul(h))
+    }
+
+    // GCM with full 16 byte tags over any 16 byte block cipher
+    pub struct Gcm<C> {
+        cipher: C,
+        h: Gf128
+    }
+
+    impl<C: BlockCipher> Gcm<C> {
+        pub fn new(cipher: C) -> Gcm<C> {
+            assert_eq!(16, cipher.block_size());
+            let mut h = [0u8, ..16];
+            cipher.encrypt_block(h.as_mut_slice());
+            Gcm { cipher: cipher, h: Gf128::from_bytes(h) }
+        }
+
+        // The GHASH key H = E(k, 0)
+        pub fn hash_key(&self) -> Gf128 {
+            self.h
+        }
+
+        // A 96 bit nonce becomes the pre-counter block J0 directly, any other
+        // length is hashed into one. Keystream block 0 masks the tag and
+        // the message is encrypted from block 1 on, with a 32 bit counter.
+        fn ctr(&self, nonce: &[u8]) -> Ctr<&C> {
+            assert!(nonce.len() > 0);
+            let j0 = if nonce.len() == 12 {
+                let mut j0 = nonce.to_vec();
+                j0.push_all([0, 0, 0, 1]);
+                j0
+            } else {
+                ghash(&self.h, [], nonce).to_bytes()
+            };
+            let counter = j0.slice_from(12).iter().fold(0u64, |c, b| (c << 8) | *b as u64);
+            let layout = CtrLayout { nonce_len: 12, counter_len: 4, endian: Endian::Big };
+            Ctr::new(&self.cipher, layout, j0.slice_to(12), counter)
+        }
+
+        fn tag(&self, ctr: &Ctr<&C>, aad: &[u8], ciphertext: &[u8]) -> Vec<u8> {
+            let mask = Gf128::from_bytes(ctr.keystream_block(0)[]);
+            ghash(&self.h, aad, ciphertext).add(&mask).to_bytes()
+        }
+
+        // Returns the ciphertext and the tag
+        pub fn encrypt(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> (Vec<u8>, Vec<u8>) {
+            let ctr = self.ctr(nonce);
+            let ciphertext = ctr.apply_at(16, plaintext);
+            let tag = self.tag(&ctr, aad, ciphertext[]);
+            (ciphertext, tag)
+        }
+
+        pub fn decrypt(&self, nonce: &[u8], aad: &[u8], ciphertext: &[u8], tag: &[u8]) -> Result<Vec<u8>, BadTag> {
+            let ctr = self.ctr(nonce);
+            if !tags_match(self.tag(&ctr, aad, ciphertext)[], tag) {
+                return Err(BadTag);
+            }
+            Ok(ctr.apply_at(16, ciphertext))
+        }
+    }
+
+    // AES-GCM shorthands, with the key length picking the AES variant. A
+    // bad key panics; use Gcm::new with aes_for_key to get it as an error.
+    pub fn gcm_encrypt(key: &[u8], nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        Gcm::new(aes_for_key(key).unwrap()).encrypt(nonce, aad, plaintext)
+    }
+
+    pub fn gcm_decrypt(key: &[u8], nonce: &[u8], aad: &[u8], ciphertext: &[u8], tag: &[u8]) -> Result<Vec<u8>, BadTag> {
+        Gcm::new(aes_for_key(key).unwrap()).decrypt(nonce, aad, ciphertext, tag)
+    }
+
+    // Polynomials over GF(2^128) are kept lowest coefficient first, with
+    // no zero coefficients at the top, so the zero polynomial is empty
+    fn poly_trim(mut p: Vec<Gf128>) -> Vec<Gf128> {
+        while p.len() > 0 && p[p.len()-1].is_zero() {
+            p.pop();
+        }
+        p
+    }
+
+    fn poly_add(a: &[Gf128], b: &[Gf128]) -> Vec<Gf128> {
+        let len = if a.len() > b.len() { a.len() } else { b.len() };
+        poly_trim(Vec::from_fn(len, |i| {
+            let x = if i < a.len() { a[i] } else { Gf128::zero() };
+            let y = if i < b.len() { b[i] } else { Gf128::zero() };
+            x.add(&y)
+        }))
+    }
+
+    fn poly_mul(a: &[Gf128], b: &[Gf128]) -> Vec<Gf128> {
+        if a.len() == 0 || b.len() == 0 {
+            return Vec::new();
+        }
+        let mut product = Vec::from_elem(a.len() + b.len() - 1, Gf128::zero());
+        for i in range(0u,a.len())
+        {
+            for j in range(0u,b.len())
+            {
+                product[i+j] = product[i+j].add(&a[i].mul(&b[j]));
+            }
+        }
+        poly_trim(product)
+    }
+
+    // Quotient and remainder of a / b
+    fn poly_divmod(a: &[Gf128], b: &[Gf128]) -> (Vec<Gf128>, Vec<Gf128>) {
+        assert!(b.len() > 0);
+        let lead_inverse = b[b.len()-1].inverse();
+        let mut remainder = a.to_vec();
+        let mut quotient = Vec::from_elem(if a.len() >= b.len() { a.len() - b.len() + 1 } else { 0 },
+                                          Gf128::zero());
+        while remainder.len() >= b.len()
+        {
+            let shift = remainder.len() - b.len();
+            let coefficient = remainder[remainder.len()-1].mul(&lead_inverse);
+            quotient[shift] = coefficient;
+            for i in range(0u,b.len())
+            {
+                remainder[shift+i] = remainder[shift+i].add(&coefficient.mul(&b[i]));
+            }
+            remainder = poly_trim(remainder);
+        }
+        (poly_trim(quotient), remainder)
+    }
+
+    fn poly_mod(a: &[Gf128], b: &[Gf128]) -> Vec<Gf128> {
+        let (_, remainder) = poly_divmod(a, b);
+        remainder
+    }
+
+    fn poly_monic(p: &[Gf128]) -> Vec<Gf128> {
+        if p.len() == 0 {
+            return Vec::new();
+        }
+        let lead_inverse = p[p.len()-1].inverse();
+        p.iter().map(|c| c.mul(&lead_inverse)).collect()
+    }
+
+    fn poly_gcd(a: &[Gf128], b: &[Gf128]) -> Vec<Gf128> {
+        let mut a = a.to_vec();
+        let mut b = b.to_vec();
+        while b.len() > 0
+        {
+            let remainder = poly_mod(a[], b[]);
+            a = b;
+            b = remainder;
+        }
+        poly_monic(a[])
+    }
+
+    // The distinct roots of a polynomial, by Cantor-Zassenhaus
+    pub fn poly_roots(p: &[Gf128]) -> Vec<Gf128> {
+        let p = poly_monic(poly_trim(p.to_vec())[]);
+        if p.len() < 2 {
+            return Vec::new();
+        }
+
+        // x^(2^128) - x is zero everywhere in the field, so its gcd with p
+        // is the product of p's distinct linear factors
+        let x = vec![Gf128::zero(), Gf128::one()];
+        let mut frobenius = x.clone();
+        for _ in range(0u,128)
+        {
+            frobenius = poly_mod(poly_mul(frobenius[], frobenius[])[], p[]);
+        }
+        let linear = poly_gcd(poly_add(frobenius[], x[])[], p[]);
+
+        let mut roots: Vec<Gf128> = Vec::new();
+        split_linear_factors(linear, &mut roots);
+        roots
+    }
+
+    // p is monic and a product of distinct linear factors. The trace of a*x
+    // is 0 or 1 at each root, and 0 at about half of them for a random a,
+    // so its gcd with p usually splits p in two.
+    fn split_linear_factors(p: Vec<Gf128>, roots: &mut Vec<Gf128>) {
+        if p.len() < 2 {
+            return;
+        }
+        if p.len() == 2 {
+            // x + c has the root c, since -c = c
+            roots.push(p[0]);
+            return;
+        }
+        loop {
+            let mut term = poly_mod([Gf128::zero(), Gf128::random()], p[]);
+            let mut trace = term.clone();
+            for _ in range(1u,128)
+            {
+                term = poly_mod(poly_mul(term[], term[])[], p[]);
+                trace = poly_add(trace[], term[]);
+            }
+            let factor = poly_gcd(trace[], p[]);
+            if factor.len() > 1 && factor.len() < p.len() {
+                let (rest, _) = poly_divmod(p[], factor[]);
+                split_linear_factors(factor, roots);
+                split_linear_factors(rest, roots);
+                return;
+            }
+        }
+    }
+
+    pub struct GcmMessage {
+        pub aad: Vec<u8>,
+        pub ciphertext: Vec<u8>,
+        pub tag: Vec<u8>
+    }
+
+    // The tag plus GHASH, as a polynomial in H. Under a given key and nonce
+    // this comes to the same mask E(k, J0) for every message.
+    fn tag_polynomial(msg: &GcmMessage) -> Vec<Gf128> {
+        let mut p = vec![Gf128::from_bytes(msg.tag[])];
+        for block in ghash_blocks(msg.aad[], msg.ciphertext[]).iter().rev() {
+            p.push(*block);
+        }
+        p
+    }
+
+    // The "forbidden attack" on a reused nonce: the masks cancel when two
+    // tag polynomials are added, so H is a root of the sum. There are
+    // usually only a few roots, and a forgery can tell them apart.
+    pub fn recover_hash_key_candidates(first: &GcmMessage, second: &GcmMessage) -> Vec<Gf128> {
+        poly_roots(poly_add(tag_polynomial(first)[], tag_polynomial(second)[])[])
+    }
+
+    // Given H, the mask falls out of any message sent under the reused
+    // nonce, and any other aad and ciphertext can be tagged with it
+    pub fn forge_tag(h: &Gf128, known: &GcmMessage, aad: &[u8], ciphertext: &[u8]) -> Vec<u8> {
+        let mask = Gf128::from_bytes(known.tag[]).add(&ghash(h, known.aad[], known.ciphertext[]));
+        ghash(h, aad, ciphertext).add(&mask).to_bytes()
+    }
+
+    #[test]
+    fn test_gf128()
+    {
+        let a = Gf128::random();
+        let b = Gf128::random();
+        let c = Gf128::random();
+        assert_eq!(a, a.mul(&Gf128::one()));
+        assert_eq!(a.mul(&b), b.mul(&a));
+        assert_eq!(a.mul(&b.add(&c)), a.mul(&b).add(&a.mul(&c)));
+        assert_eq!(Gf128::one(), a.mul(&a.inverse()));
+        assert_eq!(a, Gf128::from_bytes(a.to_bytes()[]));
+
+        // x^127 * x wraps around to x^7 + x^2 + x + 1
+        let x = Gf128::from_bytes([0x40]);
+        let x127 = Gf128 { hi: 0, lo: 1 };
+        assert_eq!(Gf128::from_bytes([0xe1]), x127.mul(&x));
+
+        // (x - a)(x - b)(x - c) has exactly the roots a, b and c
+        let mut p = poly_mul([a, Gf128::one()], [b, Gf128::one()]);
+        p = poly_mul(p[], [c, Gf128::one()]);
+        let mut roots = poly_roots(p[]);
+        roots.sort_by(|x, y| (x.hi, x.lo).cmp(&(y.hi, y.lo)));
+        let mut expected = vec![a, b, c];
+        expected.sort_by(|x, y| (x.hi, x.lo).cmp(&(y.hi, y.lo)));
+        assert_eq!(expected, roots);
+    }
+
+    #[test]
+    fn test_gcm_mode()
+    {
+        use serialize::hex::FromHex;
+
+        // Test case 1 of the GCM spec: all zeros
+        let (ciphertext, tag) = gcm_encrypt([0u8, ..16], [0u8, ..12], [], []);
+        assert_eq!(0, ciphertext.len());
+        assert_eq!("58e2fccefa7e3061367f1d57a4e7455a".from_hex().unwrap(), tag);
+
+        // Test cases 4 and 6: aad, a partial last block, and a 60 byte nonce
+        let key = "feffe9928665731c6d6a8f9467308308".from_hex().unwrap();
+        let plaintext = "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a721c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b39"
+            .from_hex()
+            .unwrap();
+        let aad = "feedfacedeadbeeffeedfacedeadbeefabaddad2".from_hex().unwrap();
+
+        let nonce = "cafebabefacedbaddecaf888".from_hex().unwrap();
+        let (ciphertext, tag) = gcm_encrypt(key[], nonce[], aad[], plaintext[]);
+        assert_eq!("42831ec2217774244b7221b784d0d49ce3aa212f2c02a4e035c17e2329aca12e21d514b25466931c7d8f6a5aac84aa051ba30b396a0aac973d58e091"
+                   .from_hex().unwrap(), ciphertext);
+        assert_eq!("5bc94fbc3221a5db94fae95ae7121a47".from_hex().unwrap(), tag);
+        assert_eq!(Ok(plaintext.clone()), gcm_decrypt(key[], nonce[], aad[], ciphertext[], tag[]));
+
+        let long_nonce = "9313225df88406e555909c5aff5269aa6a7a9538534f7da1e4c303d2a318a728c3c0c95156809539fcf0e2429a6b525416aedbf5a0de6a57a637b39b"
+            .from_hex()
+            .unwrap();
+        let (ciphertext6, tag6) = gcm_encrypt(key[], long_nonce[], aad[], plaintext[]);
+        assert_eq!("8ce24998625615b603a033aca13fb894be9112a5c3a211a8ba262a3cca7e2ca701e4a9a4fba43c90ccdcb281d48c7c6fd62875d2aca417034c34aee5"
+                   .from_hex().unwrap(), ciphertext6);
+        assert_eq!("619cc5aefffe0bfa462af43c1699d050".from_hex().unwrap(), tag6);
+
+        // Any change to the ciphertext, aad or tag is caught
+        let mut tampered = ciphertext.clone();
+        tampered[5] ^= 1;
+        assert_eq!(Err(BadTag), gcm_decrypt(key[], nonce[], aad[], tampered[], tag[]));
+        assert_eq!(Err(BadTag), gcm_decrypt(key[], nonce[], aad.slice_from(1), ciphertext[], tag[]));
+        let mut bad_tag = tag.clone();
+        bad_tag[15] ^= 0x80;
+        assert_eq!(Err(BadTag), gcm_decrypt(key[], nonce[], aad[], ciphertext[], bad_tag[]));
+    }
+
+    #[test]
+    fn test_gcm_forbidden_attack()
+    {
+        let key = Vec::from_fn(16, |_| rand::random::<u8>());
+        let gcm = Gcm::new(aes_for_key(key[]).unwrap());
+        let nonce = [7u8, ..12];
+
+        let seal = |aad: &[u8], plaintext: &[u8]| -> GcmMessage {
+            let (ciphertext, tag) = gcm.encrypt(nonce, aad, plaintext);
+            GcmMessage { aad: aad.to_vec(), ciphertext: ciphertext, tag: tag }
+        };
+        let first = seal("user=alice".as_bytes(), "transfer 100 to bob, memo: rent".as_bytes());
+        let second = seal("user=carol".as_bytes(), "transfer 5 to dave".as_bytes());
+
+        let candidates = recover_hash_key_candidates(&first, &second);
+        assert!(candidates.contains(&gcm.hash_key()));
+
+        // CTR lets us rewrite the known plaintext, and one of the candidate
+        // keys gives it a tag that the receiver accepts
+        let wanted = "transfer 999 to eve, memo: rent".as_bytes();
+        let known = "transfer 100 to bob, memo: rent".as_bytes();
+        let forged_ciphertext = super::xor::repeat_key_xor(
+            super::xor::repeat_key_xor(known, wanted)[],
+            first.ciphertext[]);
+        let aad = "user=admin".as_bytes();
+        let accepted = candidates
+            .iter()
+            .map(|h| forge_tag(h, &first, aad, forged_ciphertext[]))
+            .filter_map(|tag| gcm.decrypt(nonce, aad, forged_ciphertext[], tag[]).ok())
+            .collect::<Vec<Vec<u8>>>();
+        assert_eq!(vec![wanted.to_vec()], accepted);
+    }
+}
+
+pub mod keywrap {
+    use super::crypto::{aes_for_key, BlockCipher};
+
+    // The RFC 3394 integrity check value, and the fixed half of the RFC
+    // 5649 one, which ends with the unpadded length instead
+    const DEFAULT_IV: [u8, ..8] = [0xa6, ..8];
+    const PADDED_IV_PREFIX: [u8, ..4] = [0xa6, 0x59, 0x59, 0xa6];
+
+    #[deriving(Show, PartialEq)]
+    pub enum UnwrapError {
+        BadLength(uint),
+        IntegrityCheckFailed
+    }
+
+    fn xor_counter(register: &[u8], t: u64) -> Vec<u8> {
+        Vec::from_fn(8, |i| register[i] ^ (t >> (56 - 8*i)) as u8)
+    }
+
+    // The wrapping function W of RFC 3394: six passes over the 64 bit
+    // blocks, each one encrypted along with the integrity register, which
+    // then has the step count xored into it
+    fn wrap_blocks<C: BlockCipher>(cipher: &C, iv: &[u8], data: &[u8]) -> Vec<u8> {
+        let n = data.len() / 8;
+        let mut register = iv.to_vec();
+        let mut blocks = data.chunks(8).map(|b| b.to_vec()).collect::<Vec<Vec<u8>>>();
+        for j in range(0u,6)
+        {
+            for i in range(0u,n)
+            {
+                let mut b = register.clone();
+                b.push_all(blocks[i][]);
+                cipher.encrypt_block(b.as_mut_slice());
+                register = xor_counter(b.slice_to(8), (n*j + i + 1) as u64);
+                blocks[i] = b.slice_from(8).to_vec();
+            }
+        }
+
+        let mut wrapped = register;
+        for block in blocks.iter() {
+            wrapped.push_all(block[]);
+        }
+        wrapped
+    }
+
+    // W run backwards, giving the integrity register and the data
+    fn unwrap_blocks<C: BlockCipher>(cipher: &C, wrapped: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let n = wrapped.len() / 8 - 1;
+        let mut register = wrapped.slice_to(8).to_vec();
+        let mut blocks = wrapped.slice_from(8).chunks(8).map(|b| b.to_vec()).collect::<Vec<Vec<u8>>>();
+        for j in range(0u,6).rev()
+        {
+            for i in range(0u,n).rev()
+            {
+                let mut b = xor_counter(register[], (n*j + i + 1) as u64);
+                b.push_all(blocks[i][]);
+                cipher.decrypt_block(b.as_mut_slice());
+                register = b.slice_to(8).to_vec();
+                blocks[i] = b.slice_from(8).to_vec();
+            }
+        }
+
+        let mut data: Vec<u8> = Vec::with_capacity(n*8);
+        for block in blocks.iter() {
+            data.push_all(block[]);
+        }
+        (register, data)
+    }
+
+    // RFC 3394 key wrap, for key data of two or more 64 bit blocks
+    pub fn wrap_with<C: BlockCipher>(cipher: &C, key_data: &[u8]) -> Vec<u8> {
+        assert_eq!(16, cipher.block_size());
+        assert!(key_data.len() >= 16 && key_data.len() % 8 == 0);
+        wrap_blocks(cipher, DEFAULT_IV, key_data)
+    }
+
+    pub fn unwrap_with<C: BlockCipher>(cipher: &C, wrapped: &[u8]) -> Result<Vec<u8>, UnwrapError> {
+        assert_eq!(16, cipher.block_size());
+        if wrapped.len() < 24 || wrapped.len() % 8 != 0 {
+            return Err(UnwrapError::BadLength(wrapped.len()));
+        }
+        let (iv, key_data) = unwrap_blocks(cipher, wrapped);
+        if iv[] != DEFAULT_IV[] {
+            return Err(UnwrapError::IntegrityCheckFailed);
+        }
+        Ok(key_data)
+    }
+
+    // RFC 5649 key wrap with padding, for key data of any length. Data that
+    // pads out to a single 64 bit block is encrypted with the iv directly.
+    pub fn wrap_padded_with<C: BlockCipher>(cipher: &C, key_data: &[u8]) -> Vec<u8> {
+        assert_eq!(16, cipher.block_size());
+        assert!(key_data.len() > 0 && (key_data.len() as u64) < (1u64 << 32));
+
+        let len = key_data.len();
+        let mut iv = PADDED_IV_PREFIX.to_vec();
+        for i in range(0u,4) {
+            iv.push((len >> (24 - 8*i)) as u8);
+        }
+        let mut padded = key_data.to_vec();
+        padded.grow((8 - len % 8) % 8, 0);
+
+        if padded.len() == 8 {
+            let mut block = iv;
+            block.push_all(padded[]);
+            cipher.encrypt_block(block.as_mut_slice());
+            block
+        } else {
+            wrap_blocks(cipher, iv[], padded[])
+        }
+    }
+
+    pub fn unwrap_padded_with<C: BlockCipher>(cipher: &C, wrapped: &[u8]) -> Result<Vec<u8>, UnwrapError> {
+        assert_eq!(16, cipher.block_size());
+        if wrapped.len() < 16 || wrapped.len() % 8 != 0 {
+            return Err(UnwrapError::BadLength(wrapped.len()));
+        }
+
+        let (iv, mut padded) = if wrapped.len() == 16 {
+            let mut block = wrapped.to_vec();
+            cipher.decrypt_block(block.as_mut_slice());
+            (block.slice_to(8).to_vec(), block.slice_from(8).to_vec())
+        } else {
+            unwrap_blocks(cipher, wrapped)
+        };
+
+        // The length has to fall in the last block, with zeros after it
+        if iv.slice_to(4) != PADDED_IV_PREFIX[] {
+            return Err(UnwrapError::IntegrityCheckFailed);
+        }
+        let len = iv.slice_from(4).iter().fold(0u, |l, b| (l << 8) | *b as uint);
+        if len > padded.len() || len + 8 <= padded.len() {
+            return Err(UnwrapError::IntegrityCheckFailed);
+        }
+        if padded.slice_from(len).iter().any(|b| *b != 0) {
+            return Err(UnwrapError::IntegrityCheckFailed);
+        }
+        padded.truncate(len);
+        Ok(padded)
+    }
+
+    // AES shorthands, with the length of the key encryption key picking
+    // the AES variant. A bad key encryption key panics; use aes_for_key
+    // with the _with functions to get it as an error.
+    pub fn wrap(kek: &[u8], key_data: &[u8]) -> Vec<u8> {
+        wrap_with(&aes_for_key(kek).unwrap(), key_data)
+    }
+
+    pub fn unwrap(kek: &[u8], wrapped: &[u8]) -> Result<Vec<u8>, UnwrapError> {
+        unwrap_with(&aes_for_key(kek).unwrap(), wrapped)
+    }
+
+    pub fn wrap_padded(kek: &[u8], key_data: &[u8]) -> Vec<u8> {
+        wrap_padded_with(&aes_for_key(kek).unwrap(), key_data)
+    }
+
+    pub fn unwrap_padded(kek: &[u8], wrapped: &[u8]) -> Result<Vec<u8>, UnwrapError> {
+        unwrap_padded_with(&aes_for_key(kek).unwrap(), wrapped)
+    }
+
+    #[test]
+    fn test_key_wrap()
+    {
+        use serialize::hex::FromHex;
+
+        // RFC 3394 4.1 and 4.6
+        let kek = "000102030405060708090a0b0c0d0e0f".from_hex().unwrap();
+        let key_data = "00112233445566778899aabbccddeeff".from_hex().unwrap();
+        let wrapped = wrap(kek[], key_data[]);
+        assert_eq!("1fa68b0a8112b447aef34bd8fb5a7b829d3e862371d2cfe5".from_hex().unwrap(), wrapped);
+        assert_eq!(Ok(key_data.clone()), unwrap(kek[], wrapped[]));
+
+        let kek256 = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f".from_hex().unwrap();
+        let key_data256 = "00112233445566778899aabbccddeeff000102030405060708090a0b0c0d0e0f".from_hex().unwrap();
+        let wrapped256 = wrap(kek256[], key_data256[]);
+        assert_eq!("28c9f404c4b810f4cbccb35cfb87f8263f5786e2d80ed326cbc7f0e71a99f43bfb988b9b7a02dd21".from_hex().unwrap(),
+                   wrapped256);
+        assert_eq!(Ok(key_data256), unwrap(kek256[], wrapped256[]));
+
+        // Tampering, the wrong key and bad lengths are all caught
+        let mut tampered = wrapped.clone();
+        tampered[20] ^= 1;
+        assert_eq!(Err(UnwrapError::IntegrityCheckFailed), unwrap(kek[], tampered[]));
+        assert_eq!(Err(UnwrapError::IntegrityCheckFailed), unwrap(kek256.slice_from(16), wrapped[]));
+        assert_eq!(Err(UnwrapError::BadLength(23)), unwrap(kek[], wrapped.slice_to(23)));
+        assert_eq!(Err(UnwrapError::BadLength(16)), unwrap(kek[], wrapped.slice_to(16)));
+    }
+
+    #[test]
+    fn test_key_wrap_with_padding()
+    {
+        use serialize::hex::FromHex;
+
+        // RFC 5649 section 6
+        let kek = "5840df6e29b02af1ab493b705bf16ea1ae8338f4dcc176a8".from_hex().unwrap();
+        let key_data = "c37b7e6492584340bed12207808941155068f738".from_hex().unwrap();
+        let wrapped = wrap_padded(kek[], key_data[]);
+        assert_eq!("138bdeaa9b8fa7fc61f97742e72248ee5ae6ae5360d1ae6a5f54f373fa543b6a".from_hex().unwrap(), wrapped);
+        assert_eq!(Ok(key_data), unwrap_padded(kek[], wrapped[]));
+
+        let short_key = "466f7250617369".from_hex().unwrap();
+        let wrapped_short = wrap_padded(kek[], short_key[]);
+        assert_eq!("afbeb0f07dfbf5419200f2ccb50bb24f".from_hex().unwrap(), wrapped_short);
+        assert_eq!(Ok(short_key), unwrap_padded(kek[], wrapped_short[]));
+
+        for len in range(1u,41)
+        {
+            let key_data = Vec::from_fn(len, |i| i as u8);
+            let wrapped = wrap_padded(kek[], key_data[]);
+            assert_eq!(8 + (len + 7)/8*8, wrapped.len());
+            assert_eq!(Ok(key_data), unwrap_padded(kek[], wrapped[]));
+        }
+
+        // A length that doesn't end in the last block, or padding that
+        // isn't zero, fails the check even though the iv prefix is right
+        let cipher = aes_for_key(kek[]).unwrap();
+        let data = [1u8, ..16];
+        let too_long = wrap_blocks(&cipher, [0xa6, 0x59, 0x59, 0xa6, 0, 0, 0, 20], data);
+        assert_eq!(Err(UnwrapError::IntegrityCheckFailed), unwrap_padded(kek[], too_long[]));
+        let nonzero_padding = wrap_blocks(&cipher, [0xa6, 0x59, 0x59, 0xa6, 0, 0, 0, 9], data);
+        assert_eq!(Err(UnwrapError::IntegrityCheckFailed), unwrap_padded(kek[], nonzero_padding[]));
+        let plain_wrap = wrap(kek[], data);
+        assert_eq!(Err(UnwrapError::IntegrityCheckFailed), unwrap_padded(kek[], plain_wrap[]));
+    }
+}
+
+pub mod chacha {
+    use std::cmp;
+    use super::crypto::{tags_match, BadTag};
+
+    // ChaCha20 as in RFC 8439: a 256 bit key, a 96 bit nonce and a 32 bit
+    // block counter, giving 64 bytes of keystream per block
+    pub struct ChaCha20 {
+        key: [u32, ..8],
+        nonce: [u32, ..3],
+        initial_counter: u32
+    }
+
+    fn read_le_words(bytes: &[u8], words: &mut [u32]) {
+        for (i, word) in words.iter_mut().enumerate()
+        {
+            *word = bytes.slice(4*i, 4*i + 4)
+                .iter()
+                .rev()
+                .fold(0u32, |w, b| (w << 8) | *b as u32);
+        }
+    }
+
+    fn quarter_round(state: &mut [u32], a: uint, b: uint, c: uint, d: uint) {
+        state[a] += state[b]; state[d] ^= state[a]; state[d] = state[d].rotate_left(16);
+        state[c] += state[d]; state[b] ^= state[c]; state[b] = state[b].rotate_left(12);
+        state[a] += state[b]; state[d] ^= state[a]; state[d] = state[d].rotate_left(8);
+        state[c] += state[d]; state[b] ^= state[c]; state[b] = state[b].rotate_left(7);
+    }
+
+    impl ChaCha20 {
+        pub fn new(key: &[u8], nonce: &[u8], initial_counter: u32) -> ChaCha20 {
+            assert_eq!(32, key.len());
+            assert_eq!(12, nonce.len());
+            let mut cipher = ChaCha20 { key: [0, ..8], nonce: [0, ..3], initial_counter: initial_counter };
+            read_le_words(key, cipher.key.as_mut_slice());
+            read_le_words(nonce, cipher.nonce.as_mut_slice());
+            cipher
+        }
+
+        // The counter wraps around within its 32 bits
+        pub fn keystream_block(&self, block_no: u32) -> Vec<u8> {
+            let mut state = [0u32, ..16];
+            // "expand 32-byte k"
+            state[0] = 0x61707865;
+            state[1] = 0x3320646e;
+            state[2] = 0x79622d32;
+            state[3] = 0x6b206574;
+            for i in range(0u,8) {
+                state[4+i] = self.key[i];
+            }
+            state[12] = self.initial_counter + block_no;
+            for i in range(0u,3) {
+                state[13+i] = self.nonce[i];
+            }
+
+            let mut working = state;
+            for _ in range(0u,10)
+            {
+                quarter_round(working.as_mut_slice(), 0, 4, 8, 12);
+                quarter_round(working.as_mut_slice(), 1, 5, 9, 13);
+                quarter_round(working.as_mut_slice(), 2, 6, 10, 14);
+                quarter_round(working.as_mut_slice(), 3, 7, 11, 15);
+                quarter_round(working.as_mut_slice(), 0, 5, 10, 15);
+                quarter_round(working.as_mut_slice(), 1, 6, 11, 12);
+                quarter_round(working.as_mut_slice(), 2, 7, 8, 13);
+                quarter_round(working.as_mut_slice(), 3, 4, 9, 14);
+            }
+
+            let mut block: Vec<u8> = Vec::with_capacity(64);
+            for i in range(0u,16)
+            {
+                let word = working[i] + state[i];
+                for shift in range(0u,4) {
+                    block.push((word >> 8*shift) as u8);
+                }
+            }
+            block
+        }
+
+        // Encrypts or decrypts data sitting at the given byte offset of the
+        // stream, in the same way as Ctr::apply_at
+        pub fn apply_at(&self, offset: u64, data: &[u8]) -> Vec<u8> {
+            let mut output: Vec<u8> = Vec::with_capacity(data.len());
+            let mut block_no = (offset / 64) as u32;
+            let mut skip = (offset % 64) as uint;
+            while output.len() < data.len()
+            {
+                let keystream = self.keystream_block(block_no);
+                let take = cmp::min(64 - skip, data.len() - output.len());
+                for i in range(0u,take)
+                {
+                    let byte = data[output.len()] ^ keystream[skip + i];
+                    output.push(byte);
+                }
+                skip = 0;
+                block_no += 1;
+            }
+            output
+        }
+
+        pub fn apply(&self, data: &[u8]) -> Vec<u8> {
+            self.apply_at(0, data)
+        }
+    }
+
+    const LIMB_MASK: u64 = 0x3ffffff;
+
+    // An integer mod 2^130 - 5, held as five 26 bit limbs, least
+    // significant first, and always fully reduced
+    #[deriving(Clone, PartialEq, Show)]
+    pub struct Fe1305 {
+        limbs: [u64, ..5]
+    }
+
+    impl Fe1305 {
+        pub fn zero() -> Fe1305 {
+            Fe1305 { limbs: [0, ..5] }
+        }
+
+        pub fn one() -> Fe1305 {
+            Fe1305 { limbs: [1, 0, 0, 0, 0] }
+        }
+
+        // Little endian, and only the low 130 bits are used
+        pub fn from_bytes(bytes: &[u8]) -> Fe1305 {
+            let mut limbs = [0u64, ..5];
+            for (i, byte) in bytes.iter().enumerate().take(17)
+            {
+                let (limb, shift) = (8*i / 26, 8*i % 26);
+                limbs[limb] |= ((*byte as u64) << shift) & LIMB_MASK;
+                if shift > 18 && limb < 4 {
+                    limbs[limb+1] |= *byte as u64 >> (26 - shift);
+                }
+            }
+            Fe1305::reduce(limbs)
+        }
+
+        // 17 bytes, little endian
+        pub fn to_bytes(&self) -> Vec<u8> {
+            Vec::from_fn(17, |i| {
+                let (limb, shift) = (8*i / 26, 8*i % 26);
+                let mut byte = self.limbs[limb] >> shift;
+                if shift > 18 && limb < 4 {
+                    byte |= self.limbs[limb+1] << (26 - shift);
+                }
+                byte as u8
+            })
+        }
+
+        // Carry oversized limbs upwards, folding anything past 2^130 back
+        // in at the bottom times 5, then subtract p if needed
+        fn reduce(mut limbs: [u64, ..5]) -> Fe1305 {
+            loop {
+                for i in range(0u,4)
+                {
+                    limbs[i+1] += limbs[i] >> 26;
+                    limbs[i] &= LIMB_MASK;
+                }
+                let carry = limbs[4] >> 26;
+                if carry == 0 {
+                    break;
+                }
+                limbs[4] &= LIMB_MASK;
+                limbs[0] += carry * 5;
+            }
+
+            // x >= p exactly when x + 5 reaches 2^130
+            let mut plus_five = limbs;
+            plus_five[0] += 5;
+            for i in range(0u,4)
+            {
+                plus_five[i+1] += plus_five[i] >> 26;
+                plus_five[i] &= LIMB_MASK;
+            }
+            if plus_five[4] >> 26 != 0 {
+                plus_five[4] &= LIMB_MASK;
+                limbs = plus_five;
+            }
+            Fe1305 { limbs: limbs }
+        }
+
+        pub fn add(&self, other: &Fe1305) -> Fe1305 {
+            let mut limbs = self.limbs;
+            for i in range(0u,5) {
+                limbs[i] += other.limbs[i];
+            }
+            Fe1305::reduce(limbs)
+        }
+
+        // 2p - x, limb by limb, so no limb goes negative
+        pub fn neg(&self) -> Fe1305 {
+            let mut limbs = [2*LIMB_MASK, ..5];
+            limbs[0] -= 8;
+            for i in range(0u,5) {
+                limbs[i] -= self.limbs[i];
+            }
+            Fe1305::reduce(limbs)
+        }
+
+        pub fn sub(&self, other: &Fe1305) -> Fe1305 {
+            self.add(&other.neg())
+        }
+
+        // Schoolbook, with 2^130 = 5 folding the top half straight back in
+        pub fn mul(&self, other: &Fe1305) -> Fe1305 {
+            let mut limbs = [0u64, ..5];
+            for i in range(0u,5)
+            {
+                for j in range(0u,5)
+                {
+                    let product = self.limbs[i] * other.limbs[j];
+                    if i + j < 5 {
+                        limbs[i+j] += product;
+                    } else {
+                        limbs[i+j-5] += 5 * product;
+                    }
+                }
+            }
+            Fe1305::reduce(limbs)
+        }
+
+        // x^(p-2), with p - 2 = 2^130 - 7
+        pub fn inverse(&self) -> Fe1305 {
+            assert!(*self != Fe1305::zero());
+            let mut exponent = Vec::from_elem(17, 0xffu8);
+            exponent[0] = 0xf9;
+            exponent[16] = 0x03;
+
+            let mut result = Fe1305::one();
+            for byte in exponent.iter().rev()
+            {
+                for bit in range(0u,8).rev()
+                {
+                    result = result.mul(&result);
+                    if (*byte >> bit) & 1 == 1 {
+                        result = result.mul(self);
+                    }
+                }
+            }
+            result
+        }
+    }
+
+    fn clamp(r: &[u8]) -> Vec<u8> {
+        let mut clamped = r.to_vec();
+        for i in [3u, 7, 11, 15].iter() {
+            clamped[*i] &= 0x0f;
+        }
+        for i in [4u, 8, 12].iter() {
+            clamped[*i] &= 0xfc;
+        }
+        clamped
+    }
+
+    // A message block with the extra 1 byte on the end
+    fn poly1305_block(chunk: &[u8]) -> Fe1305 {
+        let mut block = chunk.to_vec();
+        block.push(1);
+        Fe1305::from_bytes(block[])
+    }
+
+    // The message evaluated as a polynomial at r, before s is added
+    fn poly1305_sum(r: &Fe1305, msg: &[u8]) -> Fe1305 {
+        msg.chunks(16).fold(Fe1305::zero(), |acc, chunk| acc.add(&poly1305_block(chunk)).mul(r))
+    }
+
+    fn add_mod_2_128(a: &[u8], b: &[u8]) -> Vec<u8> {
+        let mut carry = 0u;
+        Vec::from_fn(16, |i| {
+            let sum = a[i] as uint + b[i] as uint + carry;
+            carry = sum >> 8;
+            sum as u8
+        })
+    }
+
+    fn sub_mod_2_128(a: &[u8], b: &[u8]) -> Vec<u8> {
+        // a + !b + 1
+        let mut one = [0u8, ..16];
+        one[0] = 1;
+        let not_b = b.iter().map(|x| !*x).collect::<Vec<u8>>();
+        add_mod_2_128(a, add_mod_2_128(not_b[], one)[])
+    }
+
+    // Poly1305 with a 32 byte one-time key, r followed by s
+    pub fn poly1305(key: &[u8], msg: &[u8]) -> Vec<u8> {
+        assert_eq!(32, key.len());
+        let r = Fe1305::from_bytes(clamp(key.slice_to(16))[]);
+        add_mod_2_128(poly1305_sum(&r, msg).to_bytes().slice_to(16), key.slice_from(16))
+    }
+
+    // The AEAD's one-time Poly1305 key is the start of keystream block 0,
+    // and the message itself is encrypted from block 1 on
+    fn aead_mac(key: &[u8], nonce: &[u8], aad: &[u8], ciphertext: &[u8]) -> Vec<u8> {
+        let one_time_key = ChaCha20::new(key, nonce, 0).keystream_block(0);
+
+        let mut mac_data = aad.to_vec();
+        mac_data.grow((16 - aad.len() % 16) % 16, 0);
+        mac_data.push_all(ciphertext);
+        mac_data.grow((16 - ciphertext.len() % 16) % 16, 0);
+        for len in [aad.len() as u64, ciphertext.len() as u64].iter() {
+            for shift in range(0u,8) {
+                mac_data.push((*len >> 8*shift) as u8);
+            }
+        }
+        poly1305(one_time_key.slice_to(32), mac_data[])
+    }
+
+    // Returns the ciphertext and the tag
+    pub fn chacha20_poly1305_encrypt(key: &[u8], nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let ciphertext = ChaCha20::new(key, nonce, 1).apply(plaintext);
+        let tag = aead_mac(key, nonce, aad, ciphertext[]);
+        (ciphertext, tag)
+    }
+
+    pub fn chacha20_poly1305_decrypt(key: &[u8], nonce: &[u8], aad: &[u8], ciphertext: &[u8], tag: &[u8]) -> Result<Vec<u8>, BadTag> {
+        if !tags_match(aead_mac(key, nonce, aad, ciphertext)[], tag) {
+            return Err(BadTag);
+        }
+        Ok(ChaCha20::new(key, nonce, 1).apply(ciphertext))
+    }
+
+    // A Poly1305 key must only ever be used once. With two messages of up
+    // to one block under the same key, tag1 - tag2 = (m1 - m2) * r, give
+    // or take the multiple of 2^128 lost when each tag was truncated, so
+    // trying each multiple gives r and then s. Returns every 32 byte key
+    // that agrees with both tags.
+    pub fn recover_reused_poly1305_key(msg1: &[u8], tag1: &[u8], msg2: &[u8], tag2: &[u8]) -> Vec<Vec<u8>> {
+        assert!(msg1.len() > 0 && msg1.len() <= 16);
+        assert!(msg2.len() > 0 && msg2.len() <= 16);
+        assert!(msg1 != msg2);
+
+        let diff_inverse = poly1305_block(msg1).sub(&poly1305_block(msg2)).inverse();
+        let tag_diff = Fe1305::from_bytes(tag1).sub(&Fe1305::from_bytes(tag2));
+        let mut two_128_bytes = [0u8, ..17];
+        two_128_bytes[16] = 1;
+        let two_128 = Fe1305::from_bytes(two_128_bytes);
+
+        // The sum plus s is under 2^130 + 2^128, so each tag dropped 0 to 4
+        // multiples of 2^128
+        let mut lost = tag_diff;
+        for _ in range(0u,4) {
+            lost = lost.sub(&two_128);
+        }
+
+        let mut keys: Vec<Vec<u8>> = Vec::new();
+        for _ in range(0u,9)
+        {
+            let r = lost.mul(&diff_inverse);
+            let r_bytes = r.to_bytes();
+            if r_bytes[16] == 0 && clamp(r_bytes.slice_to(16)) == r_bytes.slice_to(16) {
+                let s = sub_mod_2_128(tag1, poly1305_sum(&r, msg1).to_bytes().slice_to(16));
+                let mut key = r_bytes.slice_to(16).to_vec();
+                key.push_all(s[]);
+                if poly1305(key[], msg2)[] == tag2 {
+                    keys.push(key);
+                }
+            }
+            lost = lost.add(&two_128);
+        }
+        keys
+    }
+
+    #[test]
+    fn test_chacha20()
+    {
+        use serialize::hex::FromHex;
+
+        // RFC 8439 2.3.2 and 2.4.2
+        let key = Vec::from_fn(32, |i| i as u8);
+        let cipher = ChaCha20::new(key[], "000000090000004a00000000".from_hex().unwrap()[], 1);
+        assert_eq!("10f1e7e4d13b5915500fdd1fa32071c4c7d1f4c733c068030422aa9ac3d46c4ed2826446079faa0914c2d705d98b02a2b5129cd1de164eb9cbd083e8a2503c4e"
+                   .from_hex().unwrap(),
+                   cipher.keystream_block(0));
+
+        let cipher = ChaCha20::new(key[], "000000000000004a00000000".from_hex().unwrap()[], 1);
+        let plaintext = "Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.".as_bytes();
+        let ciphertext = "6e2e359a2568f98041ba0728dd0d6981e97e7aec1d4360c20a27afccfd9fae0bf91b65c5524733ab8f593dabcd62b3571639d624e65152ab8f530c359f0861d807ca0dbf500d6a6156a38e088a22b65e52bc514d16ccf806818ce91ab77937365af90bbf74a35be6b40b8eedf2785e42874d"
+            .from_hex()
+            .unwrap();
+        assert_eq!(ciphertext, cipher.apply(plaintext));
+        assert_eq!(plaintext, cipher.apply(ciphertext[])[]);
+        assert_eq!(ciphertext.slice_from(70), cipher.apply_at(70, plaintext.slice_from(70))[]);
+    }
+
+    #[test]
+    fn test_poly1305()
+    {
+        use serialize::hex::FromHex;
+
+        // RFC 8439 2.5.2, then two from appendix A.3 where the sum wraps
+        // past 2^130 - 5
+        let key = "85d6be7857556d337f4452fe42d506a80103808afb0db2fd4abff6af4149f51b".from_hex().unwrap();
+        assert_eq!("a8061dc1305136c6c22b8baf0c0127a9".from_hex().unwrap(),
+                   poly1305(key[], "Cryptographic Forum Research Group".as_bytes()));
+
+        let mut key = Vec::from_elem(32, 0u8);
+        key[0] = 2;
+        let mut tag = Vec::from_elem(16, 0u8);
+        tag[0] = 3;
+        assert_eq!(tag, poly1305(key[], [0xffu8, ..16]));
+        for i in range(16u,32) {
+            key[i] = 0xff;
+        }
+        assert_eq!(tag, poly1305(key[], key.slice_to(16)));
+
+        let a = Fe1305::from_bytes([0x12u8, ..16]);
+        assert_eq!(Fe1305::one(), a.mul(&a.inverse()));
+        assert_eq!(Fe1305::zero(), a.sub(&a));
+        assert_eq!(a, Fe1305::from_bytes(a.to_bytes()[]));
+        let p = "fbffffffffffffffffffffffffffffff03".from_hex().unwrap();
+        assert_eq!(Fe1305::zero(), Fe1305::from_bytes(p[]));
+    }
+
+    #[test]
+    fn test_chacha20_poly1305()
+    {
+        use serialize::hex::FromHex;
+
+        // RFC 8439 2.8.2
+        let key = Vec::from_fn(32, |i| 0x80 + i as u8);
+        let nonce = "070000004041424344454647".from_hex().unwrap();
+        let aad = "50515253c0c1c2c3c4c5c6c7".from_hex().unwrap();
+        let plaintext = "Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.".as_bytes();
+        let (ciphertext, tag) = chacha20_poly1305_encrypt(key[], nonce[], aad[], plaintext);
+        assert_eq!("d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d63dbea45e8ca9671282fafb69da92728b1a71de0a9e060b2905d6a5b67ecd3b3692ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc3ff4def08e4b7a9de576d26586cec64b6116"
+                   .from_hex().unwrap(), ciphertext);
+        assert_eq!("1ae10b594f09e26a7e902ecbd0600691".from_hex().unwrap(), tag);
+        assert_eq!(Ok(plaintext.to_vec()), chacha20_poly1305_decrypt(key[], nonce[], aad[], ciphertext[], tag[]));
+
+        let mut tampered = ciphertext.clone();
+        tampered[0] ^= 1;
+        assert_eq!(Err(BadTag), chacha20_poly1305_decrypt(key[], nonce[], aad[], tampered[], tag[]));
+        assert_eq!(Err(BadTag), chacha20_poly1305_decrypt(key[], nonce[], aad.slice_to(11), ciphertext[], tag[]));
+    }
+
+    #[test]
+    fn test_reused_poly1305_key()
+    {
+        use std::rand;
+
+        let key = Vec::from_fn(32, |_| rand::random::<u8>());
+        let msg1 = Vec::from_fn(16, |_| rand::random::<u8>());
+        let msg2 = "attack at dawn".as_bytes();
+        let tag1 = poly1305(key[], msg1[]);
+        let tag2 = poly1305(key[], msg2);
+
+        // Only the clamped part of r matters, so the real key is recovered
+        // in that form, and a tag can then be made for any message at all
+        let mut clamped_key = clamp(key.slice_to(16));
+        clamped_key.push_all(key.slice_from(16));
+        let candidates = recover_reused_poly1305_key(msg1[], tag1[], msg2, tag2[]);
+        assert!(candidates.contains(&clamped_key));
+
+        let forged_msg = "attack at dusk, and bring the whole army along".as_bytes();
+        let real_tag = poly1305(key[], forged_msg);
+        assert!(candidates.iter().any(|k| poly1305(k[], forged_msg) == real_tag));
+    }
+}
+
+pub mod rc4 {
+    pub struct Rc4 {
+        state: Vec<u8>,
+        i: u8,
+        j: u8
+    }
+
+    impl Rc4 {
+        pub fn new(key: &[u8]) -> Rc4 {
+            assert!(key.len() > 0 && key.len() <= 256);
+            let mut state = Vec::from_fn(256, |i| i as u8);
+            let mut j = 0u8;
+            for i in range(0u,256)
+            {
+                j += state[i] + key[i % key.len()];
+                state.as_mut_slice().swap(i, j as uint);
+            }
+            Rc4 { state: state, i: 0, j: 0 }
+        }
+
+        pub fn next_byte(&mut self) -> u8 {
+            self.i += 1;
+            self.j += self.state[self.i as uint];
+            self.state.as_mut_slice().swap(self.i as uint, self.j as uint);
+            self.state[(self.state[self.i as uint] + self.state[self.j as uint]) as uint]
+        }
+
+        pub fn keystream(&mut self, len: uint) -> Vec<u8> {
+            Vec::from_fn(len, |_| self.next_byte())
+        }
+
+        pub fn apply(&mut self, data: &[u8]) -> Vec<u8> {
+            data.iter().map(|b| *b ^ self.next_byte()).collect()
+        }
+    }
+
+    pub fn rc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+        Rc4::new(key).apply(data)
+    }
+
+    // How often each byte value has been seen at each of a number of
+    // positions, for attacks that pile up many samples
+    pub struct ByteTally {
+        counts: Vec<u64>
+    }
+
+    impl ByteTally {
+        pub fn new(positions: uint) -> ByteTally {
+            ByteTally { counts: Vec::from_elem(positions*256, 0) }
+        }
+
+        pub fn positions(&self) -> uint {
+            self.counts.len() / 256
+        }
+
+        pub fn add_at(&mut self, pos: uint, byte: u8) {
+            self.counts[pos*256 + byte as uint] += 1;
+        }
+
+        // Tallies sample[pos] at every position the sample reaches
+        pub fn add(&mut self, sample: &[u8]) {
+            for (pos, byte) in sample.iter().take(self.positions()).enumerate() {
+                self.add_at(pos, *byte);
+            }
+        }
+
+        pub fn count(&self, pos: uint, byte: u8) -> u64 {
+            self.counts[pos*256 + byte as uint]
+        }
+
+        pub fn samples(&self, pos: uint) -> u64 {
+            self.counts.slice(pos*256, pos*256 + 256).iter().fold(0, |a, b| a + *b)
+        }
+
+        pub fn most_common(&self, pos: uint) -> u8 {
+            range(0u,256)
+                .max_by(|byte| self.counts[pos*256 + *byte])
+                .unwrap() as u8
+        }
+    }
+
+    // Single byte biases in the early RC4 keystream: the 16th byte is 240
+    // and the 32nd is 224 noticeably more often than 1 time in 256
+    const Z16_BIAS: (uint, u8) = (15, 240);
+    const Z32_BIAS: (uint, u8) = (31, 224);
+
+    pub struct CookieRecovery {
+        pub cookie: Vec<u8>,
+        pub oracle_queries: uint
+    }
+
+    // Recover up to 32 bytes that an oracle appends to our request before
+    // encrypting it all under a fresh RC4 key (challenge 56). Padding the
+    // request slides each cookie byte under a biased keystream position, and
+    // the commonest ciphertext byte there, xored with the bias, is our guess.
+    // The stronger Z16 bias is used for the first 16 bytes and Z32 after.
+    pub fn recover_cookie_from_biases(cookie_len: uint,
+                                      samples: uint,
+                                      oracle: |request: &[u8]| -> Vec<u8>) -> CookieRecovery {
+        assert!(cookie_len <= Z32_BIAS.0 + 1);
+        let mut z16 = ByteTally::new(cookie_len);
+        let mut z32 = ByteTally::new(cookie_len);
+        let mut oracle_queries = 0u;
+
+        for padding in range(0u,Z16_BIAS.0 + 1)
+        {
+            let request = Vec::from_elem(padding, 'A' as u8);
+            let z16_byte = Z16_BIAS.0 - padding;
+            let z32_byte = Z32_BIAS.0 - padding;
+            if z16_byte >= cookie_len && z32_byte >= cookie_len {
+                continue;
+            }
+            for _ in range(0u,samples)
+            {
+                let ciphertext = oracle(request[]);
+                oracle_queries += 1;
+                if z16_byte < cookie_len {
+                    z16.add_at(z16_byte, ciphertext[Z16_BIAS.0]);
+                }
+                if z32_byte < cookie_len {
+                    z32.add_at(z32_byte, ciphertext[Z32_BIAS.0]);
+                }
+            }
+        }
+
+        let cookie = range(0u,cookie_len)
+            .map(|i| {
+                if i <= Z16_BIAS.0 {
+                    z16.most_common(i) ^ Z16_BIAS.1
+                } else {
+                    z32.most_common(i) ^ Z32_BIAS.1
+                }
+            })
+            .collect::<Vec<u8>>();
+        CookieRecovery { cookie: cookie, oracle_queries: oracle_queries }
+    }
+
+    #[test]
+    fn test_rc4()
+    {
+        use serialize::hex::FromHex;
+
+        assert_eq!("bbf316e8d940af0ad3".from_hex().unwrap(), rc4("Key".as_bytes(), "Plaintext".as_bytes()));
+        assert_eq!("1021bf0420".from_hex().unwrap(), rc4("Wiki".as_bytes(), "pedia".as_bytes()));
+        assert_eq!("45a01f645fc35b383552544b9bf5".from_hex().unwrap(),
+                   rc4("Secret".as_bytes(), "Attack at dawn".as_bytes()));
+
+        // RFC 6229, 40 bit key, offsets 0 and 16
+        let mut cipher = Rc4::new([1, 2, 3, 4, 5]);
+        assert_eq!("b2396305f03dc027ccc3524a0a1118a8".from_hex().unwrap(), cipher.keystream(16));
+        assert_eq!("6982944f18fc82d589c403a47a0d0919".from_hex().unwrap(), cipher.keystream(16));
+    }
+
+    #[test]
+    fn test_byte_tally()
+    {
+        let mut tally = ByteTally::new(3);
+        tally.add("abc".as_bytes());
+        tally.add("abd".as_bytes());
+        tally.add("xbdzz".as_bytes());
+        tally.add_at(0, 'x' as u8);
+        tally.add_at(0, 'x' as u8);
+
+        assert_eq!(3, tally.positions());
+        assert_eq!(5, tally.samples(0));
+        assert_eq!(3, tally.samples(2));
+        assert_eq!(3, tally.count(0, 'x' as u8));
+        assert_eq!(0, tally.count(1, 'z' as u8));
+        assert_eq!('x' as u8, tally.most_common(0));
+        assert_eq!('b' as u8, tally.most_common(1));
+        assert_eq!('d' as u8, tally.most_common(2));
+    }
+
+    #[test]
+    fn test_recover_cookie_from_biases()
+    {
+        // With a keystream that is always 240 at byte 16 and 224 at byte 32
+        // the attack needs just one sample per padding length
+        let cookie = "0123456789abcdefghijklmnopqrstuv".as_bytes();
+        let recovered = recover_cookie_from_biases(cookie.len(), 1, |request: &[u8]| {
+            let mut plaintext = request.to_vec();
+            plaintext.push_all(cookie);
+            let mut keystream = Vec::from_fn(plaintext.len(), |i| (i * 7) as u8);
+            keystream[15] = 240;
+            keystream[31] = 224;
+            super::xor::repeat_key_xor(keystream[], plaintext[])
+        });
+        assert_eq!(cookie, recovered.cookie[]);
+        assert_eq!(16, recovered.oracle_queries);
+    }
+}
+
+pub mod sha1 {
+    // SHA-1 as described in FIPS 180-4. The compression function and the
+    // chaining state are exposed so that a hash can be picked up from any
+    // state, which is what length extension needs.
+
+    use std::default::Default;
+
+    pub const BLOCK_SIZE: uint = 64;
+    pub const DIGEST_SIZE: uint = 20;
+    pub const INITIAL_STATE: [u32, ..5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+    pub fn compress(state: &mut [u32, ..5], block: &[u8]) {
+        assert_eq!(block.len(), BLOCK_SIZE);
+        let mut w = [0u32, ..80];
+        for t in range(0u,16) {
+            w[t] = block.slice(4*t, 4*t + 4).iter().fold(0u32, |word, b| (word << 8) | *b as u32);
+        }
+        for t in range(16u,80) {
+            w[t] = (w[t-3] ^ w[t-8] ^ w[t-14] ^ w[t-16]).rotate_left(1);
+        }
+
+        let (mut a, mut b, mut c, mut d, mut e) = (state[0], state[1], state[2], state[3], state[4]);
+        for t in range(0u,80)
+        {
+            let (f, k) = match t / 20 {
+                0 => ((b & c) | (!b & d), 0x5a827999),
+                1 => (b ^ c ^ d, 0x6ed9eba1),
+                2 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6u32)
+            };
+            let temp = a.rotate_left(5) + f + e + k + w[t];
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        state[0] += a;
+        state[1] += b;
+        state[2] += c;
+        state[3] += d;
+        state[4] += e;
+    }
+
+    // The padding that follows a message of message_len bytes: a 1 bit,
+    // zeros up to 8 bytes short of a block boundary, then the message
+    // length in bits as a big endian 64 bit number
+    pub fn padding(message_len: u64) -> Vec<u8> {
+        let mut padding = vec![0x80u8];
+        while (message_len as uint + padding.len()) % BLOCK_SIZE != BLOCK_SIZE - 8 {
+            padding.push(0);
+        }
+        for i in range(0u,8) {
+            padding.push(((message_len * 8) >> (56 - 8*i)) as u8);
+        }
+        padding
+    }
+
+    pub fn state_from_digest(digest: &[u8]) -> [u32, ..5] {
+        assert_eq!(digest.len(), DIGEST_SIZE);
+        let mut state = [0u32, ..5];
+        for i in range(0u,5) {
+            state[i] = digest.slice(4*i, 4*i + 4).iter().fold(0u32, |word, b| (word << 8) | *b as u32);
+        }
+        state
+    }
+
+    #[deriving(Clone)]
+    pub struct Sha1 {
+        state: [u32, ..5],
+        buffer: Vec<u8>,
+        length: u64
+    }
+
+    impl Sha1 {
+        pub fn new() -> Sha1 {
+            Sha1::from_state(INITIAL_STATE, 0)
+        }
+
+        pub fn from_state(state: [u32, ..5], processed_len: u64) -> Sha1 {
+            assert_eq!(processed_len % BLOCK_SIZE as u64, 0);
+            Sha1 { state: state, buffer: Vec::with_capacity(BLOCK_SIZE), length: processed_len }
+        }
+
+        pub fn state(&self) -> [u32, ..5] {
+            self.state
+        }
+
+        pub fn processed_len(&self) -> u64 {
+            self.length
+        }
+
+        pub fn update(&mut self, data: &[u8]) {
+            self.length += data.len() as u64;
+            for byte in data.iter()
+            {
+                self.buffer.push(*byte);
+                if self.buffer.len() == BLOCK_SIZE {
+                    compress(&mut self.state, self.buffer[]);
+                    self.buffer.clear();
+                }
+            }
+        }
+
+        pub fn digest(&self) -> Vec<u8> {
+            let mut finished = self.clone();
+            finished.update(padding(self.length)[]);
+            assert_eq!(0, finished.buffer.len());
+
+            let mut digest: Vec<u8> = Vec::with_capacity(DIGEST_SIZE);
+            for word in finished.state.iter() {
+                for i in range(0u,4) {
+                    digest.push((*word >> (24 - 8*i)) as u8);
+                }
+            }
+            digest
+        }
+    }
+
+    impl Default for Sha1 {
+        fn default() -> Sha1 { Sha1::new() }
+    }
+
+    pub fn sha1(data: &[u8]) -> Vec<u8> {
+        let mut hash = Sha1::new();
+        hash.update(data);
+        hash.digest()
+    }
+
+    // MAC(key, message) = SHA1(key || message)
+    pub fn secret_prefix_mac(key: &[u8], message: &[u8]) -> Vec<u8> {
+        let mut hash = Sha1::new();
+        hash.update(key);
+        hash.update(message);
+        hash.digest()
+    }
+
+    #[test]
+    fn test_sha1()
+    {
+        use serialize::hex::FromHex;
+
+        assert_eq!("da39a3ee5e6b4b0d3255bfef95601890afd80709".from_hex().unwrap(), sha1([]));
+        assert_eq!("a9993e364706816aba3e25717850c26c9cd0d89d".from_hex().unwrap(), sha1("abc".as_bytes()));
+        assert_eq!("84983e441c3bd26ebaae4aa1f95129e5e54670f1".from_hex().unwrap(),
+                   sha1("abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq".as_bytes()));
+
+        // Fed in pieces that don't line up with the blocks
+        let mut hash = Sha1::new();
+        let a = [0x61u8, ..1000];
+        for _ in range(0u,1000) {
+            hash.update(a);
+        }
+        assert_eq!(1000000, hash.processed_len());
+        assert_eq!("34aa973cd4c4daa4f61eeb2bdbad27316534016f".from_hex().unwrap(), hash.digest());
+    }
+
+    #[test]
+    fn test_sha1_internal_state()
+    {
+        let message = "Pay Alice 10 coins".as_bytes();
+        let glue = padding(message.len() as u64);
+        assert_eq!(64, message.len() + glue.len());
+        assert_eq!(0x80, glue[0]);
+        assert_eq!(vec![0u8, 0, 0, 0, 0, 0, 0, 0x90], glue.slice_from(glue.len() - 8).to_vec());
+
+        // Compressing the padded message by hand gives the same state as
+        // the digest, and hashing can carry on from there
+        let mut state = INITIAL_STATE;
+        let mut block = message.to_vec();
+        block.push_all(glue[]);
+        compress(&mut state, block[]);
+        assert_eq!(state, state_from_digest(sha1(message)[]));
+
+        let mut resumed = Sha1::from_state(state, 64);
+        resumed.update(" and Mallory 1000".as_bytes());
+        block.push_all(" and Mallory 1000".as_bytes());
+        assert_eq!(sha1(block[]), resumed.digest());
+        assert_eq!(81, resumed.processed_len());
+    }
+}
+
+pub mod md4 {
+    // MD4 from RFC 1320. Unlike the SHA family, words and the length are
+    // little endian.
+
+    use std::default::Default;
+
+    pub const BLOCK_SIZE: uint = 64;
+    pub const DIGEST_SIZE: uint = 16;
+    pub const INITIAL_STATE: [u32, ..4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+
+    fn read_words(bytes: &[u8], words: &mut [u32]) {
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = bytes.slice(4*i, 4*i + 4).iter().rev().fold(0u32, |word, b| (word << 8) | *b as u32);
+        }
+    }
+
+    pub fn compress(state: &mut [u32, ..4], block: &[u8]) {
+        assert_eq!(block.len(), BLOCK_SIZE);
+        let mut x = [0u32, ..16];
+        read_words(block, x.as_mut_slice());
+
+        let (mut a, mut b, mut c, mut d) = (state[0], state[1], state[2], state[3]);
+        for i in range(0u,48)
+        {
+            let round = i / 16;
+            let step = i % 16;
+            let (f, k, shift) = match round {
+                0 => ((b & c) | (!b & d), step, [3u, 7, 11, 19][step % 4]),
+                1 => ((b & c) | (b & d) | (c & d), 4*(step % 4) + step/4, [3u, 5, 9, 13][step % 4]),
+                _ => (b ^ c ^ d, [0u, 8, 4, 12, 2, 10, 6, 14, 1, 9, 5, 13, 3, 11, 7, 15][step], [3u, 9, 11, 15][step % 4])
+            };
+            let constant = [0u32, 0x5a827999, 0x6ed9eba1][round];
+            let temp = (a + f + x[k] + constant).rotate_left(shift);
+            a = d;
+            d = c;
+            c = b;
+            b = temp;
+        }
+
+        state[0] += a;
+        state[1] += b;
+        state[2] += c;
+        state[3] += d;
+    }
+
+    // The same as SHA-1 padding except that the bit length is little endian
+    pub fn padding(message_len: u64) -> Vec<u8> {
+        let mut padding = vec![0x80u8];
+        while (message_len as uint + padding.len()) % BLOCK_SIZE != BLOCK_SIZE - 8 {
+            padding.push(0);
+        }
+        for i in range(0u,8) {
+            padding.push(((message_len * 8) >> (8*i)) as u8);
+        }
+        padding
+    }
+
+    pub fn state_from_digest(digest: &[u8]) -> [u32, ..4] {
+        assert_eq!(digest.len(), DIGEST_SIZE);
+        let mut state = [0u32, ..4];
+        read_words(digest, state.as_mut_slice());
+        state
+    }
+
+    #[deriving(Clone)]
+    pub struct Md4 {
+        state: [u32, ..4],
+        buffer: Vec<u8>,
+        length: u64
+    }
+
+    impl Md4 {
+        pub fn new() -> Md4 {
+            Md4::from_state(INITIAL_STATE, 0)
+        }
+
+        pub fn from_state(state: [u32, ..4], processed_len: u64) -> Md4 {
+            assert_eq!(processed_len % BLOCK_SIZE as u64, 0);
+            Md4 { state: state, buffer: Vec::with_capacity(BLOCK_SIZE), length: processed_len }
+        }
+
+        pub fn state(&self) -> [u32, ..4] {
+            self.state
+        }
+
+        pub fn processed_len(&self) -> u64 {
+            self.length
+        }
+
+        pub fn update(&mut self, data: &[u8]) {
+            self.length += data.len() as u64;
+            for byte in data.iter()
+            {
+                self.buffer.push(*byte);
+                if self.buffer.len() == BLOCK_SIZE {
+                    compress(&mut self.state, self.buffer[]);
+                    self.buffer.clear();
+                }
+            }
+        }
+
+        pub fn digest(&self) -> Vec<u8> {
+            let mut finished = self.clone();
+            finished.update(padding(self.length)[]);
+            assert_eq!(0, finished.buffer.len());
+
+            let mut digest: Vec<u8> = Vec::with_capacity(DIGEST_SIZE);
+            for word in finished.state.iter() {
+                for i in range(0u,4) {
+                    digest.push((*word >> (8*i)) as u8);
+                }
+            }
+            digest
+        }
+    }
+
+    impl Default for Md4 {
+        fn default() -> Md4 { Md4::new() }
+    }
+
+    pub fn md4(data: &[u8]) -> Vec<u8> {
+        let mut hash = Md4::new();
+        hash.update(data);
+        hash.digest()
+    }
+
+    // MAC(key, message) = MD4(key || message)
+    pub fn secret_prefix_mac(key: &[u8], message: &[u8]) -> Vec<u8> {
+        let mut hash = Md4::new();
+        hash.update(key);
+        hash.update(message);
+        hash.digest()
+    }
+
+    #[test]
+    fn test_md4()
+    {
+        use serialize::hex::FromHex;
+
+        // From RFC 1320
+        assert_eq!("31d6cfe0d16ae931b73c59d7e0c089c0".from_hex().unwrap(), md4([]));
+        assert_eq!("bde52cb31de33e46245e05fbdbd6fb24".from_hex().unwrap(), md4("a".as_bytes()));
+        assert_eq!("a448017aaf21d8525fc10ae87aa6729d".from_hex().unwrap(), md4("abc".as_bytes()));
+        assert_eq!("d9130a8164549fe818874806e1c7014b".from_hex().unwrap(), md4("message digest".as_bytes()));
+        assert_eq!("e33b4ddc9c38f2199c3e7b164fcc0536".from_hex().unwrap(),
+                   md4("12345678901234567890123456789012345678901234567890123456789012345678901234567890".as_bytes()));
+
+        let message = "abcdefghijklmnopqrstuvwxyz".as_bytes();
+        let mut block = message.to_vec();
+        block.push_all(padding(message.len() as u64)[]);
+        let mut state = INITIAL_STATE;
+        compress(&mut state, block[]);
+        assert_eq!(state, state_from_digest(md4(message)[]));
+    }
+}
+
+pub mod sha256 {
+    // SHA-256 from FIPS 180-4, which pads messages the same way as SHA-1
+
+    use std::default::Default;
+    pub use super::sha1::padding;
+
+    pub const BLOCK_SIZE: uint = 64;
+    pub const DIGEST_SIZE: uint = 32;
+    pub const INITIAL_STATE: [u32, ..8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a,
+                                           0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+
+    static K: [u32, ..64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+        0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+        0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+        0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+        0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+        0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+        0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2
+    ];
+
+    fn read_words(bytes: &[u8], words: &mut [u32]) {
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = bytes.slice(4*i, 4*i + 4).iter().fold(0u32, |word, b| (word << 8) | *b as u32);
+        }
+    }
+
+    pub fn compress(state: &mut [u32, ..8], block: &[u8]) {
+        assert_eq!(block.len(), BLOCK_SIZE);
+        let mut w = [0u32, ..64];
+        read_words(block, w.slice_mut(0, 16));
+        for t in range(16u,64) {
+            let s0 = w[t-15].rotate_right(7) ^ w[t-15].rotate_right(18) ^ (w[t-15] >> 3);
+            let s1 = w[t-2].rotate_right(17) ^ w[t-2].rotate_right(19) ^ (w[t-2] >> 10);
+            w[t] = w[t-16] + s0 + w[t-7] + s1;
+        }
+
+        let mut v = *state;
+        for t in range(0u,64)
+        {
+            let (a, b, c, d, e, f, g, h) = (v[0], v[1], v[2], v[3], v[4], v[5], v[6], v[7]);
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let temp1 = h + s1 + ch + K[t] + w[t];
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0 + maj;
+            v = [temp1 + temp2, a, b, c, d + temp1, e, f, g];
+        }
+
+        for i in range(0u,8) {
+            state[i] += v[i];
+        }
+    }
+
+    pub fn state_from_digest(digest: &[u8]) -> [u32, ..8] {
+        assert_eq!(digest.len(), DIGEST_SIZE);
+        let mut state = [0u32, ..8];
+        read_words(digest, state.as_mut_slice());
+        state
+    }
+
+    #[deriving(Clone)]
+    pub struct Sha256 {
+        state: [u32, ..8],
+        buffer: Vec<u8>,
+        length: u64
+    }
+
+    impl Sha256 {
+        pub fn new() -> Sha256 {
+            Sha256::from_state(INITIAL_STATE, 0)
+        }
+
+        pub fn from_state(state: [u32, ..8], processed_len: u64) -> Sha256 {
+            assert_eq!(processed_len % BLOCK_SIZE as u64, 0);
+            Sha256 { state: state, buffer: Vec::with_capacity(BLOCK_SIZE), length: processed_len }
+        }
+
+        pub fn state(&self) -> [u32, ..8] {
+            self.state
+        }
+
+        pub fn processed_len(&self) -> u64 {
+            self.length
+        }
+
+        pub fn update(&mut self, data: &[u8]) {
+            self.length += data.len() as u64;
+            for byte in data.iter()
+            {
+                self.buffer.push(*byte);
+                if self.buffer.len() == BLOCK_SIZE {
+                    compress(&mut self.state, self.buffer[]);
+                    self.buffer.clear();
+                }
+            }
+        }
+
+        pub fn digest(&self) -> Vec<u8> {
+            let mut finished = self.clone();
+            finished.update(padding(self.length)[]);
+            assert_eq!(0, finished.buffer.len());
+
+            let mut digest: Vec<u8> = Vec::with_capacity(DIGEST_SIZE);
+            for word in finished.state.iter() {
+                for i in range(0u,4) {
+                    digest.push((*word >> (24 - 8*i)) as u8);
+                }
+            }
+            digest
+        }
+    }
+
+    impl Default for Sha256 {
+        fn default() -> Sha256 { Sha256::new() }
+    }
+
+    pub fn sha256(data: &[u8]) -> Vec<u8> {
+        let mut hash = Sha256::new();
+        hash.update(data);
+        hash.digest()
+    }
+
+    // MAC(key, message) = SHA256(key || message)
+    pub fn secret_prefix_mac(key: &[u8], message: &[u8]) -> Vec<u8> {
+        let mut hash = Sha256::new();
+        hash.update(key);
+        hash.update(message);
+        hash.digest()
+    }
+
+    #[test]
+    fn test_sha256()
+    {
+        use serialize::hex::FromHex;
+
+        assert_eq!("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".from_hex().unwrap(), sha256([]));
+        assert_eq!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".from_hex().unwrap(),
+                   sha256("abc".as_bytes()));
+        assert_eq!("248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1".from_hex().unwrap(),
+                   sha256("abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq".as_bytes()));
+
+        let message = "abc".as_bytes();
+        let mut block = message.to_vec();
+        block.push_all(padding(message.len() as u64)[]);
+        let mut state = INITIAL_STATE;
+        compress(&mut state, block[]);
+        assert_eq!(state, state_from_digest(sha256(message)[]));
+    }
+}
+
+pub mod length_extension {
+    // Length extension against secret prefix MACs, for any of the
+    // Merkle-Damgard hashes above. Knowing H(key || message) is knowing the
+    // chaining state after key || message || padding, so we can keep
+    // hashing from there without the key.
+
+    use std::default::Default;
+    use super::md4::Md4;
+    use super::sha1::Sha1;
+    use super::sha256::Sha256;
+
+    // A hash that can be picked up again from one of its own digests. Each
+    // hash module has the pieces behind this: compress runs one block
+    // through the compression function, state_from_digest gives back the
+    // chaining state a digest was read out of, and from_state carries on
+    // from a chaining state.
+    pub trait MerkleDamgard: Clone + Default {
+        fn block_size(&self) -> uint;
+        // The padding which is appended to a message of message_len bytes
+        fn padding(&self, message_len: u64) -> Vec<u8>;
+        // A hash whose state is read out of digest, as if processed_len
+        // bytes, a whole number of blocks, had already gone into it
+        fn resume(&self, digest: &[u8], processed_len: u64) -> Self;
+        fn update(&mut self, data: &[u8]);
+        // Pads and finishes a copy, so more can still be added after
+        fn digest(&self) -> Vec<u8>;
+    }
+
+    impl MerkleDamgard for Sha1 {
+        fn block_size(&self) -> uint { super::sha1::BLOCK_SIZE }
+        fn padding(&self, message_len: u64) -> Vec<u8> { super::sha1::padding(message_len) }
+        fn resume(&self, digest: &[u8], processed_len: u64) -> Sha1 {
+            Sha1::from_state(super::sha1::state_from_digest(digest), processed_len)
+        }
+        fn update(&mut self, data: &[u8]) { self.update(data) }
+        fn digest(&self) -> Vec<u8> { self.digest() }
+    }
+
+    impl MerkleDamgard for Md4 {
+        fn block_size(&self) -> uint { super::md4::BLOCK_SIZE }
+        fn padding(&self, message_len: u64) -> Vec<u8> { super::md4::padding(message_len) }
+        fn resume(&self, digest: &[u8], processed_len: u64) -> Md4 {
+            Md4::from_state(super::md4::state_from_digest(digest), processed_len)
+        }
+        fn update(&mut self, data: &[u8]) { self.update(data) }
+        fn digest(&self) -> Vec<u8> { self.digest() }
+    }
+
+    impl MerkleDamgard for Sha256 {
+        fn block_size(&self) -> uint { super::sha256::BLOCK_SIZE }
+        fn padding(&self, message_len: u64) -> Vec<u8> { super::sha256::padding(message_len) }
+        fn resume(&self, digest: &[u8], processed_len: u64) -> Sha256 {
+            Sha256::from_state(super::sha256::state_from_digest(digest), processed_len)
+        }
+        fn update(&mut self, data: &[u8]) { self.update(data) }
+        fn digest(&self) -> Vec<u8> { self.digest() }
+    }
+
+    pub struct Extension {
+        pub key_len: uint,
+        // message || glue padding || suffix
+        pub message: Vec<u8>,
+        pub mac: Vec<u8>
+    }
+
+    // Forge the MAC of message || glue || suffix from the MAC of message,
+    // assuming the key is key_len bytes long
+    pub fn extend<H: MerkleDamgard>(mac: &[u8], message: &[u8], key_len: uint, suffix: &[u8]) -> Extension {
+        let hash: H = Default::default();
+        let glue = hash.padding((key_len + message.len()) as u64);
+        let processed_len = key_len + message.len() + glue.len();
+        assert_eq!(processed_len % hash.block_size(), 0);
+
+        let mut forged_hash = hash.resume(mac, processed_len as u64);
+        forged_hash.update(suffix);
+
+        let mut forged = message.to_vec();
+        forged.push_all(glue[]);
+        forged.push_all(suffix);
+        Extension { key_len: key_len, message: forged, mac: forged_hash.digest() }
+    }
+
+    // One forgery for each key length from min_key_len to max_key_len
+    pub fn extend_for_key_lengths<H: MerkleDamgard>(mac: &[u8], message: &[u8],
+                                                    min_key_len: uint, max_key_len: uint,
+                                                    suffix: &[u8]) -> Vec<Extension> {
+        range(min_key_len, max_key_len + 1).map(|key_len| extend::<H>(mac, message, key_len, suffix)).collect()
+    }
+
+    // Try each key length in turn until the oracle accepts one of the
+    // forgeries, or None if none of them work
+    pub fn forge<H: MerkleDamgard>(mac: &[u8], message: &[u8],
+                                   min_key_len: uint, max_key_len: uint,
+                                   suffix: &[u8],
+                                   oracle: |message: &[u8], mac: &[u8]| -> bool) -> Option<Extension> {
+        for key_len in range(min_key_len, max_key_len + 1)
+        {
+            let extension = extend::<H>(mac, message, key_len, suffix);
+            if oracle(extension.message[], extension.mac[]) {
+                return Some(extension);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_length_extension()
+    {
+        let key = "YELLOW SUBMARINE!".as_bytes();
+        let message = "user=bob;role=guest".as_bytes();
+        let suffix = ";role=admin".as_bytes();
+
+        // Only the right key length gives a valid forgery
+        let mac = super::sha256::secret_prefix_mac(key, message);
+        let forgeries = extend_for_key_lengths::<Sha256>(mac[], message, 1, 64, suffix);
+        assert_eq!(64, forgeries.len());
+        for forgery in forgeries.iter() {
+            let valid = super::sha256::secret_prefix_mac(key, forgery.message[]) == forgery.mac;
+            assert_eq!(forgery.key_len == key.len(), valid);
+            assert!(forgery.message.slice_to(message.len()) == message);
+            assert!(forgery.message.slice_from(forgery.message.len() - suffix.len()) == suffix);
+        }
+
+        let mac = super::md4::secret_prefix_mac(key, message);
+        let forged = forge::<Md4>(mac[], message, 0, 32, suffix,
+                                  |m, t| super::md4::secret_prefix_mac(key, m)[] == t).unwrap();
+        assert_eq!(key.len(), forged.key_len);
+
+        // The key is longer than we allowed for
+        let mac = super::sha1::secret_prefix_mac(key, message);
+        assert!(forge::<Sha1>(mac[], message, 0, 16, suffix,
+                              |m, t| super::sha1::secret_prefix_mac(key, m)[] == t).is_none());
+    }
+}
+
+pub mod streaming {
+    // Incremental ECB and CBC, for data which arrives in chunks of any
+    // size, plus Reader and Writer adapters built on top.
+
+    use std::cmp;
+    use std::io;
+    use std::io::{IoError, IoResult, Reader, Writer};
+    use super::crypto;
+    use super::crypto::BlockCipher;
+    use super::pad::{PaddingError, Pkcs7Padding};
+
+    // Anything which turns a stream of chunks into output, with a final
+    // flush once the stream has ended
+    pub trait StreamTransform {
+        fn update(&mut self, data: &[u8]) -> Vec<u8>;
+        fn finalize(&mut self) -> Result<Vec<u8>, PaddingError>;
+    }
+
+    // For CBC we carry the last ciphertext block from one update to the next
+    enum Chaining {
+        Ecb,
+        Cbc(Vec<u8>)
+    }
+
+    pub struct Encryptor<C> {
+        cipher: C,
+        chaining: Chaining,
+        pending: Vec<u8>,
+        finalized: bool
+    }
+
+    impl<C: BlockCipher> Encryptor<C> {
+        pub fn ecb(cipher: C) -> Encryptor<C> {
+            Encryptor { cipher: cipher, chaining: Chaining::Ecb, pending: Vec::new(), finalized: false }
+        }
+
+        pub fn cbc(cipher: C, iv: &[u8]) -> Encryptor<C> {
+            assert_eq!(iv.len(), cipher.block_size());
+            Encryptor { cipher: cipher, chaining: Chaining::Cbc(iv.to_vec()), pending: Vec::new(), finalized: false }
+        }
+
+        // Encrypt the first len bytes of pending, which must be whole blocks
+        fn encrypt_pending(&mut self, len: uint) -> Vec<u8> {
+            let encrypted = match self.chaining {
+                Chaining::Ecb => crypto::ecb_encrypt_blocks(&self.cipher, self.pending.slice_to(len)),
+                Chaining::Cbc(ref mut iv) => {
+                    let encrypted = crypto::cbc_encrypt_blocks(&self.cipher, self.pending.slice_to(len), iv[]);
+                    if len > 0 {
+                        *iv = encrypted.slice_from(len - iv.len()).to_vec();
+                    }
+                    encrypted
+                }
+            };
+            self.pending = self.pending.slice_from(len).to_vec();
+            encrypted
+        }
+    }
+
+    impl<C: BlockCipher> StreamTransform for Encryptor<C> {
+        // Encrypts as many whole blocks as are available, keeping the rest
+        fn update(&mut self, data: &[u8]) -> Vec<u8> {
+            assert!(!self.finalized);
+            self.pending.push_all(data);
+            let block_size = self.cipher.block_size();
+            let whole_blocks_len = self.pending.len() - self.pending.len() % block_size;
+            self.encrypt_pending(whole_blocks_len)
+        }
+
+        // Pads and encrypts whatever is left over
+        fn finalize(&mut self) -> Result<Vec<u8>, PaddingError> {
+            assert!(!self.finalized);
+            self.finalized = true;
+            self.pending.pkcs7_extend(self.cipher.block_size());
+            let len = self.pending.len();
+            Ok(self.encrypt_pending(len))
+        }
+    }
+
+    pub struct Decryptor<C> {
+        cipher: C,
+        chaining: Chaining,
+        pending: Vec<u8>,
+        finalized: bool
+    }
+
+    impl<C: BlockCipher> Decryptor<C> {
+        pub fn ecb(cipher: C) -> Decryptor<C> {
+            Decryptor { cipher: cipher, chaining: Chaining::Ecb, pending: Vec::new(), finalized: false }
+        }
+
+        pub fn cbc(cipher: C, iv: &[u8]) -> Decryptor<C> {
+            assert_eq!(iv.len(), cipher.block_size());
+            Decryptor { cipher: cipher, chaining: Chaining::Cbc(iv.to_vec()), pending: Vec::new(), finalized: false }
+        }
+
+        // Decrypt the first len bytes of pending, which must be whole blocks
+        fn decrypt_pending(&mut self, len: uint) -> Vec<u8> {
+            let decrypted = match self.chaining {
+                Chaining::Ecb => crypto::ecb_decrypt_blocks(&self.cipher, self.pending.slice_to(len)),
+                Chaining::Cbc(ref mut iv) => {
+                    let decrypted = crypto::cbc_decrypt_blocks(&self.cipher, self.pending.slice_to(len), iv[]);
+                    if len > 0 {
+                        *iv = self.pending.slice(len - iv.len(), len).to_vec();
+                    }
+                    decrypted
+                }
+            };
+            self.pending = self.pending.slice_from(len).to_vec();
+            decrypted
+        }
+    }
+
+    impl<C: BlockCipher> StreamTransform for Decryptor<C> {
+        // Decrypts whole blocks, but always holds the last one back since
+        // it might turn out to be the one carrying the padding
+        fn update(&mut self, data: &[u8]) -> Vec<u8> {
+            assert!(!self.finalized);
+            self.pending.push_all(data);
+            if self.pending.len() == 0 {
+                return Vec::new();
+            }
+            let block_size = self.cipher.block_size();
+            let held_back_len = (self.pending.len() - 1) % block_size + 1;
+            let len = self.pending.len() - held_back_len;
+            self.decrypt_pending(len)
+        }
+
+        // Decrypts the final block and checks and removes the padding
+        fn finalize(&mut self) -> Result<Vec<u8>, PaddingError> {
+            assert!(!self.finalized);
+            self.finalized = true;
+            let block_size = self.cipher.block_size();
+            if self.pending.len() != block_size {
+                return Err(PaddingError::BadLength(self.pending.len()));
+            }
+            let mut decrypted = self.decrypt_pending(block_size);
+            try!(decrypted.pkcs7_trim(block_size));
+            Ok(decrypted)
+        }
+    }
+
+    fn padding_io_error(err: PaddingError) -> IoError {
+        IoError {
+            kind: io::InvalidInput,
+            desc: "invalid padding",
+            detail: Some(format!("{}", err))
+        }
+    }
+
+    // Passes everything written through the transform to the inner writer.
+    // finish() must be called at the end to write out the final block.
+    pub struct CipherWriter<W, T> {
+        inner: W,
+        transform: T
+    }
+
+    impl<W: Writer, T: StreamTransform> CipherWriter<W, T> {
+        pub fn new(inner: W, transform: T) -> CipherWriter<W, T> {
+            CipherWriter { inner: inner, transform: transform }
+        }
+
+        pub fn finish(mut self) -> IoResult<W> {
+            let output = try!(self.transform.finalize().map_err(padding_io_error));
+            try!(self.inner.write(output[]));
+            try!(self.inner.flush());
+            Ok(self.inner)
+        }
+    }
+
+    impl<W: Writer, T: StreamTransform> Writer for CipherWriter<W, T> {
+        fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+            let output = self.transform.update(buf);
+            self.inner.write(output[])
+        }
+
+        fn flush(&mut self) -> IoResult<()> {
+            self.inner.flush()
+        }
+    }
+
+    // Reads from the inner reader through the transform, finalizing it
+    // when the inner reader runs out
+    pub struct CipherReader<R, T> {
+        inner: R,
+        transform: T,
+        output: Vec<u8>,
+        position: uint,
+        finished: bool
+    }
+
+    impl<R: Reader, T: StreamTransform> CipherReader<R, T> {
+        pub fn new(inner: R, transform: T) -> CipherReader<R, T> {
+            CipherReader { inner: inner, transform: transform, output: Vec::new(), position: 0, finished: false }
+        }
+    }
+
+    impl<R: Reader, T: StreamTransform> Reader for CipherReader<R, T> {
+        fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+            while self.position == self.output.len()
+            {
+                if self.finished {
+                    return Err(io::standard_error(io::EndOfFile));
+                }
+
+                let mut chunk = [0u8, ..4096];
+                match self.inner.read(chunk.as_mut_slice()) {
+                    Ok(len) => {
+                        self.output = self.transform.update(chunk.slice_to(len));
+                    }
+                    Err(ref err) if err.kind == io::EndOfFile => {
+                        self.output = try!(self.transform.finalize().map_err(padding_io_error));
+                        self.finished = true;
+                    }
+                    Err(err) => return Err(err)
+                }
+                self.position = 0;
+            }
+
+            let len = cmp::min(buf.len(), self.output.len() - self.position);
+            for i in range(0u,len) {
+                buf[i] = self.output[self.position + i];
+            }
+            self.position += len;
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_streaming_matches_one_shot()
+    {
+        use super::aes::Aes;
+
+        let cipher = Aes::new("YELLOW SUBMARINE".as_bytes()).unwrap();
+        let iv = [5u8, ..16];
+        let msg = Vec::from_fn(100, |i| i as u8);
+        let ecb_ciphertext = crypto::ecb_encrypt_with(&cipher, msg[]);
+        let cbc_ciphertext = crypto::cbc_encrypt_with(&cipher, msg[], iv);
+
+        for chunk_size in range(1u,40)
+        {
+            let mut ecb_encryptor = Encryptor::ecb(cipher.clone());
+            let mut cbc_encryptor = Encryptor::cbc(cipher.clone(), iv);
+            let mut ecb_output: Vec<u8> = Vec::new();
+            let mut cbc_output: Vec<u8> = Vec::new();
+            for chunk in msg.chunks(chunk_size) {
+                ecb_output.push_all(ecb_encryptor.update(chunk)[]);
+                cbc_output.push_all(cbc_encryptor.update(chunk)[]);
+            }
+            ecb_output.push_all(ecb_encryptor.finalize().unwrap()[]);
+            cbc_output.push_all(cbc_encryptor.finalize().unwrap()[]);
+            assert_eq!(ecb_ciphertext, ecb_output);
+            assert_eq!(cbc_ciphertext, cbc_output);
+
+            let mut ecb_decryptor = Decryptor::ecb(cipher.clone());
+            let mut cbc_decryptor = Decryptor::cbc(cipher.clone(), iv);
+            let mut ecb_plaintext: Vec<u8> = Vec::new();
+            let mut cbc_plaintext: Vec<u8> = Vec::new();
+            for chunk in ecb_ciphertext.chunks(chunk_size) {
+                ecb_plaintext.push_all(ecb_decryptor.update(chunk)[]);
+            }
+            for chunk in cbc_ciphertext.chunks(chunk_size) {
+                cbc_plaintext.push_all(cbc_decryptor.update(chunk)[]);
+            }
+            ecb_plaintext.push_all(ecb_decryptor.finalize().unwrap()[]);
+            cbc_plaintext.push_all(cbc_decryptor.finalize().unwrap()[]);
+            assert_eq!(msg, ecb_plaintext);
+            assert_eq!(msg, cbc_plaintext);
+        }
+    }
+
+    #[test]
+    fn test_cipher_reader_writer()
+    {
+        use std::io::{MemReader, MemWriter};
+        use super::aes::Aes;
+
+        let cipher = Aes::new("YELLOW SUBMARINE".as_bytes()).unwrap();
+        let iv = [9u8, ..16];
+        let msg = "We all live in a yellow submarine, a yellow submarine".as_bytes();
+
+        let mut writer = CipherWriter::new(MemWriter::new(), Encryptor::cbc(cipher.clone(), iv));
+        for chunk in msg.chunks(5) {
+            writer.write(chunk).unwrap();
+        }
+        let ciphertext = writer.finish().unwrap().get_ref().to_vec();
+        assert_eq!(crypto::cbc_encrypt_with(&cipher, msg, iv), ciphertext);
+
+        let mut reader = CipherReader::new(MemReader::new(ciphertext.clone()), Decryptor::cbc(cipher.clone(), iv));
+        assert_eq!(msg, reader.read_to_end().unwrap()[]);
+
+        // A truncated ciphertext shows up as an error at the end of the stream
+        let truncated = ciphertext.slice_to(ciphertext.len() - 1).to_vec();
+        let mut reader = CipherReader::new(MemReader::new(truncated), Decryptor::cbc(cipher.clone(), iv));
+        assert_eq!(io::InvalidInput, reader.read_to_end().err().unwrap().kind);
+    }
 }